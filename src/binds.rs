@@ -0,0 +1,548 @@
+//! `focus-workspace` key binds read from niri's config file and the files it
+//! includes with top-level `include "<path>"` nodes, resolved relative to the
+//! including file.
+//!
+//! Only the parts of KDL that niri's `binds` block uses are understood: nodes
+//! with properties, quoted strings, `//` and `/* */` comments, and `/-` to
+//! comment out a node, argument or block. Other syntax may be misread, which
+//! at worst yields fewer or odd hints.
+
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+
+/// Key combos that focus a workspace, by index or by name
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Binds {
+    by_index: HashMap<u8, String>,
+    by_name: HashMap<String, String>,
+    /// Config files the binds were read from, including ones that couldn't be read
+    files: Vec<PathBuf>,
+}
+
+impl Binds {
+    /// Load binds from niri's config and its includes, skipping files that can't be read
+    pub(crate) fn load() -> Binds {
+        let mut binds = Binds::default();
+        if let Some(path) = config_path() {
+            binds.read(&path);
+        }
+        binds
+    }
+
+    /// Binds from a config without following its includes
+    #[cfg(test)]
+    fn parse(source: &str) -> Binds {
+        let mut binds = Binds::default();
+        binds.parse_source(source, &mut |_, _| {});
+        binds
+    }
+
+    /// The files to watch for changes to the binds
+    pub(crate) fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Add the binds from the config file at `path`, following its includes in place
+    fn read(&mut self, path: &Path) {
+        // niri rejects include cycles; reading each file once is enough here
+        let canonical = |path: &Path| std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if self.files.iter().any(|file| canonical(file) == canonical(path)) {
+            return;
+        }
+        self.files.push(path.to_path_buf());
+
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    eprintln!("Failed to read niri config {}: {}", path.display(), e);
+                }
+                return;
+            }
+        };
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        self.parse_source(&source, &mut |binds, included| binds.read(&dir.join(included)));
+    }
+
+    /// Add the binds from `source`, calling `include` with the path of each top-level `include` node
+    fn parse_source(&mut self, source: &str, include: &mut impl FnMut(&mut Binds, &str)) {
+        // Node names from the outermost block inwards
+        let mut stack: Vec<String> = Vec::new();
+        let mut statement: Vec<Token> = Vec::new();
+
+        for token in strip_slashdash(tokenize(source)) {
+            match token {
+                Token::Open => {
+                    let name = match statement.first() {
+                        Some(Token::Word(word)) => word.clone(),
+                        _ => String::new(),
+                    };
+                    statement.clear();
+                    stack.push(name);
+                }
+                Token::Close => {
+                    self.statement(&stack, &statement);
+                    statement.clear();
+                    stack.pop();
+                }
+                Token::End => {
+                    self.end_node(&stack, &statement, include);
+                    statement.clear();
+                }
+                token => statement.push(token),
+            }
+        }
+        // The last node may end without a newline
+        self.end_node(&stack, &statement, include);
+    }
+
+    fn end_node(&mut self, stack: &[String], statement: &[Token], include: &mut impl FnMut(&mut Binds, &str)) {
+        match include_path(stack, statement) {
+            Some(path) => include(self, path),
+            None => self.statement(stack, statement),
+        }
+    }
+
+    /// Record `focus-workspace <target>` when it's the action of a bind
+    fn statement(&mut self, stack: &[String], statement: &[Token]) {
+        let [.., parent, key] = stack else {
+            return;
+        };
+        if parent != "binds" {
+            return;
+        }
+        let [Token::Word(action), target, ..] = statement else {
+            return;
+        };
+        if action != "focus-workspace" {
+            return;
+        }
+
+        // The first bind for a workspace wins
+        match target {
+            Token::Word(word) => {
+                if let Ok(idx) = word.parse() {
+                    self.by_index.entry(idx).or_insert_with(|| key.clone());
+                }
+            }
+            Token::Str(name) => {
+                self.by_name.entry(name.clone()).or_insert_with(|| key.clone());
+            }
+            _ => {}
+        }
+    }
+
+    /// The bind for a workspace, preferring one that targets it by name
+    pub(crate) fn get(&self, name: Option<&str>, idx: u8) -> Option<&str> {
+        name.and_then(|name| self.by_name.get(name))
+            .or_else(|| self.by_index.get(&idx))
+            .map(String::as_str)
+    }
+}
+
+/// The path of a top-level `include "<path>"` node, ignoring properties such as `optional=true`
+fn include_path<'a>(stack: &[String], statement: &'a [Token]) -> Option<&'a str> {
+    if !stack.is_empty() {
+        return None;
+    }
+    let [Token::Word(node), args @ ..] = statement else {
+        return None;
+    };
+    if node != "include" {
+        return None;
+    }
+    args.iter().find_map(|arg| match arg {
+        Token::Str(path) => Some(path.as_str()),
+        _ => None,
+    })
+}
+
+/// `$NIRI_CONFIG`, or `niri/config.kdl` in the XDG config directory
+pub(crate) fn config_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("NIRI_CONFIG").filter(|p| !p.is_empty()) {
+        return Some(PathBuf::from(path));
+    }
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("niri").join("config.kdl"))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    /// `key=value` property; the value is irrelevant for binds
+    Property,
+    Open,
+    Close,
+    /// End of a node: `;` or a newline
+    End,
+    /// `/-`, commenting out the next node, argument, property or block
+    SlashDash,
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while let Some(&c) = chars.get(pos) {
+        match c {
+            '/' if chars.get(pos + 1) == Some(&'-') => {
+                tokens.push(Token::SlashDash);
+                pos += 2;
+            }
+            '/' if chars.get(pos + 1) == Some(&'/') => {
+                while chars.get(pos).is_some_and(|&c| c != '\n') {
+                    pos += 1;
+                }
+            }
+            '/' if chars.get(pos + 1) == Some(&'*') => {
+                pos += 2;
+                while pos < chars.len() && !(chars[pos] == '*' && chars.get(pos + 1) == Some(&'/')) {
+                    pos += 1;
+                }
+                pos += 2;
+            }
+            '\\' => {
+                // Line continuation: skip to the next line without ending the node
+                while chars.get(pos).is_some_and(|&c| c != '\n') {
+                    pos += 1;
+                }
+                pos += 1;
+            }
+            '{' => {
+                tokens.push(Token::Open);
+                pos += 1;
+            }
+            '}' => {
+                tokens.push(Token::Close);
+                pos += 1;
+            }
+            ';' | '\n' => {
+                tokens.push(Token::End);
+                pos += 1;
+            }
+            '"' => {
+                let (value, end) = read_string(&chars, pos);
+                tokens.push(Token::Str(value));
+                pos = end;
+            }
+            c if c.is_whitespace() => pos += 1,
+            _ => {
+                let start = pos;
+                while chars.get(pos).is_some_and(|&c| !c.is_whitespace() && !"{};\"=".contains(c)) {
+                    pos += 1;
+                }
+                let word: String = chars[start..pos].iter().collect();
+                if chars.get(pos) == Some(&'=') {
+                    // Skip the property value
+                    pos += 1;
+                    if chars.get(pos) == Some(&'"') {
+                        pos = read_string(&chars, pos).1;
+                    } else {
+                        while chars.get(pos).is_some_and(|&c| !c.is_whitespace() && !"{};".contains(c)) {
+                            pos += 1;
+                        }
+                    }
+                    tokens.push(Token::Property);
+                } else {
+                    tokens.push(Token::Word(word));
+                }
+            }
+        }
+    }
+
+    tokens
+}
+
+/// Drop whatever each `/-` comments out: a whole node at the start of one, otherwise
+/// the next argument, property or children block
+fn strip_slashdash(tokens: Vec<Token>) -> Vec<Token> {
+    let mut stripped = Vec::new();
+    let mut tokens = tokens.into_iter().peekable();
+    let mut node_start = true;
+
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::SlashDash if node_start => {
+                while tokens.next_if_eq(&Token::End).is_some() {}
+                skip_node(&mut tokens);
+            }
+            Token::SlashDash => match tokens.peek() {
+                Some(Token::Open) => {
+                    tokens.next();
+                    skip_block(&mut tokens);
+                }
+                Some(Token::Word(_) | Token::Str(_) | Token::Property) => {
+                    tokens.next();
+                }
+                _ => {}
+            },
+            token => {
+                node_start = matches!(token, Token::Open | Token::Close | Token::End);
+                stripped.push(token);
+            }
+        }
+    }
+
+    stripped
+}
+
+/// Skip a node through its `;`, newline or children block, stopping before the end of the enclosing block
+fn skip_node(tokens: &mut Peekable<impl Iterator<Item = Token>>) {
+    while let Some(token) = tokens.next_if(|token| *token != Token::Close) {
+        match token {
+            Token::End => return,
+            Token::Open => return skip_block(tokens),
+            _ => {}
+        }
+    }
+}
+
+/// Skip the rest of a block whose `{` was just read, through its `}`
+fn skip_block(tokens: &mut impl Iterator<Item = Token>) {
+    let mut depth = 1;
+    for token in tokens {
+        match token {
+            Token::Open => depth += 1,
+            Token::Close => {
+                depth -= 1;
+                if depth == 0 {
+                    return;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Read a quoted string starting at `start`, returning its value and the position after it
+fn read_string(chars: &[char], start: usize) -> (String, usize) {
+    let mut value = String::new();
+    let mut pos = start + 1;
+    while let Some(&c) = chars.get(pos) {
+        pos += 1;
+        match c {
+            '"' => break,
+            '\\' => {
+                if let Some(&escaped) = chars.get(pos) {
+                    value.push(match escaped {
+                        'n' => '\n',
+                        't' => '\t',
+                        other => other,
+                    });
+                    pos += 1;
+                }
+            }
+            c => value.push(c),
+        }
+    }
+    (value, pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Binds;
+    use std::path::PathBuf;
+
+    #[test]
+    fn index_and_name_binds() {
+        let binds = Binds::parse(
+            r#"
+            binds {
+                Mod+1 { focus-workspace 1; }
+                Mod+B { focus-workspace "browser"; }
+            }
+            "#,
+        );
+        assert_eq!(binds.get(None, 1), Some("Mod+1"));
+        assert_eq!(binds.get(Some("browser"), 2), Some("Mod+B"));
+        assert_eq!(binds.get(Some("mail"), 2), None);
+    }
+
+    #[test]
+    fn name_bind_wins_over_index_bind() {
+        let binds = Binds::parse(
+            r#"
+            binds {
+                Mod+1 { focus-workspace 1; }
+                Mod+B { focus-workspace "browser"; }
+            }
+            "#,
+        );
+        assert_eq!(binds.get(Some("browser"), 1), Some("Mod+B"));
+        assert_eq!(binds.get(Some("mail"), 1), Some("Mod+1"));
+    }
+
+    #[test]
+    fn first_bind_wins() {
+        let binds = Binds::parse(
+            "binds {\n    Mod+1 { focus-workspace 1; }\n    Mod+Ctrl+1 { focus-workspace 1; }\n}",
+        );
+        assert_eq!(binds.get(None, 1), Some("Mod+1"));
+    }
+
+    #[test]
+    fn comments_are_ignored() {
+        let binds = Binds::parse(
+            r#"
+            binds {
+                // Mod+1 { focus-workspace 1; }
+                /* Mod+2 { focus-workspace 2; }
+                   Mod+3 { focus-workspace 3; } */
+                Mod+4 { focus-workspace 4; } // trailing
+            }
+            "#,
+        );
+        assert_eq!(binds.get(None, 1), None);
+        assert_eq!(binds.get(None, 2), None);
+        assert_eq!(binds.get(None, 3), None);
+        assert_eq!(binds.get(None, 4), Some("Mod+4"));
+    }
+
+    #[test]
+    fn properties_are_skipped() {
+        let binds = Binds::parse(
+            r#"
+            binds {
+                Mod+1 repeat=false hotkey-overlay-title="Workspace 1" { focus-workspace 1; }
+                Mod+2 cooldown-ms=150 { focus-workspace 2; }
+            }
+            "#,
+        );
+        assert_eq!(binds.get(None, 1), Some("Mod+1"));
+        assert_eq!(binds.get(None, 2), Some("Mod+2"));
+    }
+
+    #[test]
+    fn quoted_names_with_escapes_and_spaces() {
+        let binds = Binds::parse(
+            r#"
+            binds {
+                Mod+C { focus-workspace "chat \"work\""; }
+                Mod+M { focus-workspace "music and video"; }
+            }
+            "#,
+        );
+        assert_eq!(binds.get(Some("chat \"work\""), 1), Some("Mod+C"));
+        assert_eq!(binds.get(Some("music and video"), 1), Some("Mod+M"));
+    }
+
+    #[test]
+    fn only_direct_children_of_binds() {
+        let binds = Binds::parse(
+            r#"
+            workspace "browser" {
+                focus-workspace 1
+            }
+            binds {
+                Mod+1 {
+                    focus-workspace 1
+                }
+                Mod+2 { spawn "foot"; }
+                Mod+3 {
+                    nested { focus-workspace 3; }
+                }
+            }
+            "#,
+        );
+        assert_eq!(binds.get(None, 1), Some("Mod+1"));
+        assert_eq!(binds.get(None, 2), None);
+        assert_eq!(binds.get(None, 3), None);
+    }
+
+    #[test]
+    fn slashdash_comments_out_nodes() {
+        let binds = Binds::parse(
+            r#"
+            binds {
+                /-Mod+1 { focus-workspace 1; }
+                /- Mod+Shift+1 {
+                    focus-workspace 1
+                }
+                Mod+Ctrl+1 { focus-workspace 1; }
+                Mod+2 { /-focus-workspace 3; focus-workspace 2; }
+            }
+            /-binds {
+                Mod+4 { focus-workspace 4; }
+            }
+            "#,
+        );
+        assert_eq!(binds.get(None, 1), Some("Mod+Ctrl+1"));
+        assert_eq!(binds.get(None, 2), Some("Mod+2"));
+        assert_eq!(binds.get(None, 3), None);
+        assert_eq!(binds.get(None, 4), None);
+    }
+
+    #[test]
+    fn slashdash_comments_out_arguments_and_blocks() {
+        let binds = Binds::parse(
+            r#"
+            binds {
+                Mod+1 /-repeat=false { focus-workspace 1; }
+                Mod+2 /-{ focus-workspace 5; } { focus-workspace 2; }
+                Mod+6 { focus-workspace /-6 7; }
+            }
+            "#,
+        );
+        assert_eq!(binds.get(None, 1), Some("Mod+1"));
+        assert_eq!(binds.get(None, 2), Some("Mod+2"));
+        assert_eq!(binds.get(None, 5), None);
+        assert_eq!(binds.get(None, 6), None);
+        assert_eq!(binds.get(None, 7), Some("Mod+6"));
+    }
+
+    #[test]
+    fn top_level_includes() {
+        let mut includes = Vec::new();
+        let mut binds = Binds::default();
+        binds.parse_source(
+            r#"
+            include "binds.kdl"
+            include optional=true "local.kdl"
+            /-include "old.kdl"
+            binds {
+                include "nested.kdl"
+                Mod+1 { focus-workspace 1; }
+            }
+            include "last.kdl""#,
+            &mut |binds, path| {
+                includes.push(path.to_string());
+                if path == "binds.kdl" {
+                    binds.parse_source("binds { Mod+Alt+1 { focus-workspace 1; } }", &mut |_, _| {});
+                }
+            },
+        );
+        assert_eq!(includes, ["binds.kdl", "local.kdl", "last.kdl"]);
+        // Included binds count from where the include is
+        assert_eq!(binds.get(None, 1), Some("Mod+Alt+1"));
+    }
+
+    #[test]
+    fn includes_are_read_relative_to_the_including_file() {
+        let dir = std::env::temp_dir().join(format!("niri-workspaces-binds-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("niri/conf.d")).unwrap();
+        let config = dir.join("niri/config.kdl");
+        let workspaces = dir.join("niri/conf.d/workspaces.kdl");
+        let missing = dir.join("niri/conf.d/missing.kdl");
+        std::fs::write(&config, "include \"conf.d/workspaces.kdl\"\n").unwrap();
+        std::fs::write(
+            &workspaces,
+            "include \"missing.kdl\"\ninclude \"../config.kdl\"\nbinds { Mod+1 { focus-workspace 1; } }\n",
+        )
+        .unwrap();
+
+        let mut binds = Binds::default();
+        binds.read(&config);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(binds.get(None, 1), Some("Mod+1"));
+        // The cycle back to config.kdl is read once; the missing file is still watched
+        let expected: Vec<PathBuf> = vec![config, workspaces, missing];
+        assert_eq!(binds.files(), expected);
+    }
+}
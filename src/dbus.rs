@@ -0,0 +1,307 @@
+//! Optional D-Bus interface for scripts, on the session bus.
+//!
+//! Every bar registers an object at `/org/waybar/NiriWorkspaces/<n>`, numbered
+//! from 0 in the order the bars start. The process owns the
+//! `org.waybar.NiriWorkspaces` name while any bar has an object, so a second
+//! Waybar only reaches its objects through its unique bus name.
+
+use gtk::gio;
+use gtk::glib::thread_guard::ThreadGuard;
+use gtk::glib::{ToVariant, Variant};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::sync::{Mutex, PoisonError};
+use waybar_cffi::gtk;
+
+pub(crate) const BUS_NAME: &str = "org.waybar.NiriWorkspaces";
+const INTERFACE: &str = "org.waybar.NiriWorkspaces";
+const OBJECT_PATH: &str = "/org/waybar/NiriWorkspaces";
+
+const INTROSPECTION: &str = r#"
+<node>
+  <interface name="org.waybar.NiriWorkspaces">
+    <method name="GetWorkspaces">
+      <arg type="s" name="workspaces" direction="out"/>
+    </method>
+    <method name="Refresh"/>
+    <method name="ToggleCurrentOnly">
+      <arg type="b" name="current_only" direction="out"/>
+    </method>
+    <method name="Rename">
+      <arg type="s" name="workspace" direction="in"/>
+      <arg type="s" name="name" direction="in"/>
+    </method>
+    <method name="Action">
+      <arg type="s" name="action" direction="in"/>
+    </method>
+    <signal name="Changed"/>
+  </interface>
+</node>
+"#;
+
+/// A method call from the bus
+pub(crate) enum Command {
+    /// The workspaces as the bar sees them, as JSON
+    GetWorkspaces,
+    /// Query niri and redraw
+    Refresh,
+    ToggleCurrentOnly,
+    /// Name a workspace given as `id:<id>`, `idx:<index>` or `name:<name>`; an empty name removes it
+    Rename { workspace: String, name: String },
+    /// One of the module's actions, as in Waybar's `actions`
+    Action(String),
+}
+
+pub(crate) enum Reply {
+    Done,
+    Workspaces(String),
+    CurrentOnly(bool),
+}
+
+thread_local! {
+    /// Object numbers in use by this process's bars
+    static OBJECTS: RefCell<BTreeSet<u32>> = const { RefCell::new(BTreeSet::new()) };
+    static OWNER: RefCell<Option<gio::OwnerId>> = const { RefCell::new(None) };
+}
+
+/// A bar's registered object; dropping it unregisters the object, and the bus name with the last one
+pub(crate) struct Service {
+    connection: gio::DBusConnection,
+    registration: Option<gio::RegistrationId>,
+    number: u32,
+}
+
+impl Service {
+    /// Register an object answering method calls with `handler` on the main loop
+    pub(crate) fn register(handler: impl Fn(Command) -> Result<Reply, String> + 'static) -> Result<Service, String> {
+        let connection = gio::bus_get_sync(gio::BusType::Session, None::<&gio::Cancellable>)
+            .map_err(|e| format!("Failed to connect to the session bus: {}", e))?;
+        let interface = gio::DBusNodeInfo::for_xml(INTROSPECTION)
+            .ok()
+            .and_then(|node| node.lookup_interface(INTERFACE))
+            .expect("the introspection data is valid");
+
+        let number = OBJECTS.with_borrow_mut(|objects| {
+            let number = (0..).find(|n| !objects.contains(n)).expect("fewer bars than numbers");
+            objects.insert(number);
+            number
+        });
+
+        // gio calls back on this thread's main context; the guard lets the handler hold widgets
+        let handler = Mutex::new(ThreadGuard::new(handler));
+        let registration = connection.register_object(
+            &format!("{}/{}", OBJECT_PATH, number),
+            &interface,
+            move |_, _, _, _, method, parameters, invocation| {
+                let Some(command) = parse_command(method, &parameters) else {
+                    invocation.return_dbus_error(
+                        "org.freedesktop.DBus.Error.UnknownMethod",
+                        &format!("Unknown method {}", method),
+                    );
+                    return;
+                };
+                let reply = (handler.lock().unwrap_or_else(PoisonError::into_inner).get_ref())(command);
+                match reply {
+                    Ok(Reply::Done) => invocation.return_value(None),
+                    Ok(Reply::Workspaces(json)) => invocation.return_value(Some(&(json,).to_variant())),
+                    Ok(Reply::CurrentOnly(current_only)) => {
+                        invocation.return_value(Some(&(current_only,).to_variant()))
+                    }
+                    Err(e) => invocation.return_dbus_error("org.freedesktop.DBus.Error.Failed", &e),
+                }
+            },
+            // The interface has no properties
+            |_, _, _, _, _| ().to_variant(),
+            |_, _, _, _, _, _| false,
+        );
+        let registration = match registration {
+            Ok(registration) => registration,
+            Err(e) => {
+                OBJECTS.with_borrow_mut(|objects| objects.remove(&number));
+                return Err(format!("Failed to register D-Bus object: {}", e));
+            }
+        };
+
+        if OWNER.with_borrow(Option::is_none) {
+            let owner = gio::bus_own_name_on_connection(
+                &connection,
+                BUS_NAME,
+                gio::BusNameOwnerFlags::DO_NOT_QUEUE,
+                |_, _| {},
+                |_, name| eprintln!("Couldn't own D-Bus name {}, is another Waybar using it?", name),
+            );
+            OWNER.set(Some(owner));
+        }
+
+        Ok(Service { connection, registration: Some(registration), number })
+    }
+
+    /// Tell listeners the workspaces as returned by `GetWorkspaces` changed
+    pub(crate) fn emit_changed(&self) {
+        let path = format!("{}/{}", OBJECT_PATH, self.number);
+        if let Err(e) = self.connection.emit_signal(None, &path, INTERFACE, "Changed", None) {
+            eprintln!("Failed to emit D-Bus signal: {}", e);
+        }
+    }
+}
+
+impl Drop for Service {
+    fn drop(&mut self) {
+        if let Some(registration) = self.registration.take() {
+            let _ = self.connection.unregister_object(registration);
+        }
+        let remaining = OBJECTS.with_borrow_mut(|objects| {
+            objects.remove(&self.number);
+            objects.len()
+        });
+        if remaining == 0 {
+            if let Some(owner) = OWNER.take() {
+                gio::bus_unown_name(owner);
+            }
+        }
+    }
+}
+
+fn parse_command(method: &str, parameters: &Variant) -> Option<Command> {
+    match method {
+        "GetWorkspaces" => Some(Command::GetWorkspaces),
+        "Refresh" => Some(Command::Refresh),
+        "ToggleCurrentOnly" => Some(Command::ToggleCurrentOnly),
+        "Rename" => parameters
+            .get::<(String, String)>()
+            .map(|(workspace, name)| Command::Rename { workspace, name }),
+        "Action" => parameters.get::<(String,)>().map(|(action,)| Command::Action(action)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gtk::glib;
+
+    #[test]
+    fn commands() {
+        let none = ().to_variant();
+        assert!(matches!(parse_command("GetWorkspaces", &none), Some(Command::GetWorkspaces)));
+        assert!(matches!(parse_command("Refresh", &none), Some(Command::Refresh)));
+        assert!(matches!(parse_command("ToggleCurrentOnly", &none), Some(Command::ToggleCurrentOnly)));
+        assert!(matches!(
+            parse_command("Rename", &("id:3", "mail").to_variant()),
+            Some(Command::Rename { workspace, name }) if workspace == "id:3" && name == "mail"
+        ));
+        assert!(matches!(
+            parse_command("Action", &("toggle-overview",).to_variant()),
+            Some(Command::Action(action)) if action == "toggle-overview"
+        ));
+    }
+
+    #[test]
+    fn invalid_commands() {
+        assert!(parse_command("Rename", &("id:3",).to_variant()).is_none());
+        assert!(parse_command("Rename", &(3u32, "mail").to_variant()).is_none());
+        assert!(parse_command("Action", &().to_variant()).is_none());
+        assert!(parse_command("Quit", &().to_variant()).is_none());
+    }
+
+    /// A private session bus for the test, torn down on drop
+    struct TestBus(*mut gio::ffi::GTestDBus);
+
+    impl TestBus {
+        fn up() -> TestBus {
+            unsafe {
+                let bus = gio::ffi::g_test_dbus_new(gio::ffi::G_TEST_DBUS_NONE);
+                gio::ffi::g_test_dbus_up(bus);
+                TestBus(bus)
+            }
+        }
+
+        fn address(&self) -> String {
+            unsafe { std::ffi::CStr::from_ptr(gio::ffi::g_test_dbus_get_bus_address(self.0)) }
+                .to_string_lossy()
+                .into_owned()
+        }
+    }
+
+    impl Drop for TestBus {
+        fn drop(&mut self) {
+            unsafe {
+                gio::ffi::g_test_dbus_down(self.0);
+                glib::gobject_ffi::g_object_unref(self.0.cast());
+            }
+        }
+    }
+
+    /// Call `method` on the service's object from a second connection, running the main loop
+    /// the service answers on until the reply arrives
+    fn call(
+        client: &gio::DBusConnection,
+        service: &Service,
+        main_loop: &glib::MainLoop,
+        method: &str,
+        parameters: Option<Variant>,
+    ) -> Result<Variant, glib::Error> {
+        let reply = std::rc::Rc::new(RefCell::new(None));
+        let main_loop_for_reply = main_loop.clone();
+        let reply_for_call = reply.clone();
+        client.call(
+            service.connection.unique_name().as_deref(),
+            &format!("{}/{}", OBJECT_PATH, service.number),
+            INTERFACE,
+            method,
+            parameters.as_ref(),
+            None,
+            gio::DBusCallFlags::NONE,
+            5000,
+            None::<&gio::Cancellable>,
+            move |result| {
+                reply_for_call.replace(Some(result));
+                main_loop_for_reply.quit();
+            },
+        );
+        main_loop.run();
+        reply.take().expect("the main loop runs until the reply")
+    }
+
+    #[test]
+    fn service_on_a_private_bus() {
+        let bus = TestBus::up();
+        let context = glib::MainContext::new();
+        context
+            .with_thread_default(|| {
+                let main_loop = glib::MainLoop::new(Some(&context), false);
+                let service = Service::register(|command| match command {
+                    Command::GetWorkspaces => Ok(Reply::Workspaces(r#"[{"id":1}]"#.to_string())),
+                    Command::Rename { workspace, .. } => {
+                        crate::parse_reference(&workspace, |_| Err("no niri here".to_string())).map(|_| Reply::Done)
+                    }
+                    _ => Err("unexpected command".to_string()),
+                })
+                .expect("registers on the test bus");
+
+                let client = gio::DBusConnection::for_address_sync(
+                    &bus.address(),
+                    gio::DBusConnectionFlags::AUTHENTICATION_CLIENT | gio::DBusConnectionFlags::MESSAGE_BUS_CONNECTION,
+                    None,
+                    None::<&gio::Cancellable>,
+                )
+                .expect("connects to the test bus");
+
+                let workspaces = call(&client, &service, &main_loop, "GetWorkspaces", None).expect("GetWorkspaces replies");
+                assert_eq!(workspaces.get::<(String,)>(), Some((r#"[{"id":1}]"#.to_string(),)));
+
+                let error = call(&client, &service, &main_loop, "Rename", Some(("mail", "work").to_variant()))
+                    .expect_err("Rename rejects a bare name");
+                assert!(error.message().contains("org.freedesktop.DBus.Error.Failed"), "{}", error);
+                assert!(error.message().contains("invalid workspace 'mail'"), "{}", error);
+
+                let error = call(&client, &service, &main_loop, "Rename", Some((1u32,).to_variant()))
+                    .expect_err("Rename rejects mistyped arguments");
+                assert!(error.message().contains("org.freedesktop.DBus.Error.InvalidArgs"), "{}", error);
+
+                drop(service);
+                client.close_sync(None::<&gio::Cancellable>).expect("closes the client");
+            })
+            .expect("the new context is free");
+    }
+}
@@ -0,0 +1,250 @@
+//! The niri event stream, shared by every module instance in the process.
+//!
+//! Waybar creates one instance per bar, so the stream is started by the first
+//! subscription and stopped when the last one is dropped. Waybar drops every
+//! instance when it reloads, so a reload leaves no thread or socket behind.
+//!
+//! The event thread keeps its own copy of niri's workspaces and windows up to
+//! date from the events, and sends a snapshot after each burst of events, so
+//! subscribers never need to query niri to redraw.
+
+use futures_channel::mpsc::{self, UnboundedSender};
+use futures_util::StreamExt;
+use niri_ipc::socket::SOCKET_PATH_ENV;
+use niri_ipc::state::{EventStreamStatePart, WindowsState, WorkspacesState};
+use niri_ipc::{Event, Reply, Request, Window, Workspace};
+use std::cell::{Cell, RefCell};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use waybar_cffi::gtk;
+
+/// Messages from the event thread to the main loop
+#[derive(Debug, Clone)]
+pub(crate) enum ModuleEvent {
+    /// Workspaces or windows changed
+    State(Arc<Snapshot>),
+    OverviewChanged(bool),
+    /// niri reloaded its config, so binds may have changed
+    NiriConfigLoaded,
+    Connection(ConnectionStatus),
+}
+
+/// niri's workspaces and windows after a burst of events
+#[derive(Debug)]
+pub(crate) struct Snapshot {
+    pub(crate) workspaces: Vec<Workspace>,
+    pub(crate) windows: Vec<Window>,
+    /// Number of niri events folded into this snapshot
+    pub(crate) events: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ConnectionStatus {
+    Connected,
+    /// The stream couldn't be opened or broke; the thread keeps retrying
+    Disconnected(String),
+}
+
+type Subscriber = Rc<dyn Fn(ModuleEvent)>;
+
+thread_local! {
+    static SUBSCRIBERS: RefCell<Vec<(u64, Subscriber)>> = const { RefCell::new(Vec::new()) };
+    static NEXT_ID: Cell<u64> = const { Cell::new(0) };
+    static STREAM: RefCell<Option<Stream>> = const { RefCell::new(None) };
+}
+
+/// Keeps a callback subscribed; dropping the last subscription stops the stream
+pub(crate) struct Subscription {
+    id: u64,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let remaining = SUBSCRIBERS.with_borrow_mut(|subscribers| {
+            subscribers.retain(|(id, _)| *id != self.id);
+            subscribers.len()
+        });
+        if remaining == 0 {
+            if let Some(stream) = STREAM.take() {
+                stream.stop();
+            }
+        }
+    }
+}
+
+/// Call `callback` on the main loop for every relevant niri event, starting the stream if needed
+pub(crate) fn subscribe(callback: impl Fn(ModuleEvent) + 'static) -> Subscription {
+    if STREAM.with_borrow(Option::is_none) {
+        STREAM.set(Some(Stream::start()));
+    }
+    let id = NEXT_ID.replace(NEXT_ID.get() + 1);
+    SUBSCRIBERS.with_borrow_mut(|subscribers| subscribers.push((id, Rc::new(callback))));
+    Subscription { id }
+}
+
+fn dispatch(event: ModuleEvent) {
+    // Callbacks may unsubscribe, so don't hold the borrow while calling them
+    let subscribers: Vec<Subscriber> =
+        SUBSCRIBERS.with_borrow(|subscribers| subscribers.iter().map(|(_, s)| s.clone()).collect());
+    for subscriber in subscribers {
+        subscriber(event.clone());
+    }
+}
+
+/// The event thread and the main loop task receiving its messages
+struct Stream {
+    stop: Arc<AtomicBool>,
+    /// The current connection, shut down to unblock a pending read
+    connection: Arc<Mutex<Option<UnixStream>>>,
+    thread: JoinHandle<()>,
+    receiver: gtk::glib::JoinHandle<()>,
+}
+
+impl Stream {
+    fn start() -> Stream {
+        let (tx, mut rx) = mpsc::unbounded();
+        let stop = Arc::new(AtomicBool::new(false));
+        let connection = Arc::new(Mutex::new(None));
+
+        let thread = {
+            let stop = stop.clone();
+            let connection = connection.clone();
+            thread::spawn(move || read_events(tx, &stop, &connection))
+        };
+        let receiver = gtk::glib::MainContext::default().spawn_local(async move {
+            while let Some(event) = rx.next().await {
+                dispatch(event);
+            }
+        });
+
+        Stream { stop, connection, thread, receiver }
+    }
+
+    fn stop(self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(connection) = self.connection.lock().unwrap_or_else(PoisonError::into_inner).take() {
+            let _ = connection.shutdown(Shutdown::Both);
+        }
+        // Wake the thread if it's waiting to reconnect
+        self.thread.thread().unpark();
+        self.receiver.abort();
+        if self.thread.join().is_err() {
+            eprintln!("niri event thread panicked");
+        }
+    }
+}
+
+fn read_events(tx: UnboundedSender<ModuleEvent>, stop: &AtomicBool, connection: &Mutex<Option<UnixStream>>) {
+    let send = |event| {
+        let _ = tx.unbounded_send(event);
+    };
+    let stopped = || stop.load(Ordering::SeqCst);
+
+    while !stopped() {
+        // Connect and request the event stream
+        let mut reader = match request_event_stream(stop, connection) {
+            Ok(reader) => reader,
+            Err(e) => {
+                if !stopped() {
+                    send(ModuleEvent::Connection(ConnectionStatus::Disconnected(e.to_string())));
+                }
+                wait(stop, Duration::from_secs(5));
+                continue;
+            }
+        };
+        send(ModuleEvent::Connection(ConnectionStatus::Connected));
+
+        // niri starts the stream with the full state, so start from scratch on every connection
+        let mut workspaces = WorkspacesState::default();
+        let mut windows = WindowsState::default();
+        let mut changed = 0;
+
+        // Listen for events
+        let mut line = String::new();
+        let error = loop {
+            line.clear();
+            match reader.read_line(&mut line) {
+                Ok(0) => break "niri closed the event stream".to_string(),
+                Ok(_) => {}
+                Err(e) => break e.to_string(),
+            }
+
+            // Events added in newer niri versions don't parse; they're not ones we react to
+            if let Ok(event) = serde_json::from_str::<Event>(&line) {
+                // The state helpers panic on events that don't match their state; resync if that happens
+                let applied = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                    workspaces.apply(event).and_then(|event| windows.apply(event))
+                }));
+                match applied {
+                    Ok(None) => changed += 1,
+                    Ok(Some(Event::OverviewOpenedOrClosed { is_open })) => send(ModuleEvent::OverviewChanged(is_open)),
+                    Ok(Some(Event::ConfigLoaded { failed: false })) => send(ModuleEvent::NiriConfigLoaded),
+                    Ok(Some(_)) => {}
+                    Err(_) => break "event stream out of sync".to_string(),
+                }
+            }
+
+            // Send one snapshot per burst: only once no further events are already buffered
+            if changed > 0 && reader.buffer().is_empty() {
+                send(ModuleEvent::State(Arc::new(Snapshot {
+                    workspaces: workspaces.workspaces.values().cloned().collect(),
+                    windows: windows.windows.values().cloned().collect(),
+                    events: changed,
+                })));
+                changed = 0;
+            }
+        };
+        if !stopped() {
+            send(ModuleEvent::Connection(ConnectionStatus::Disconnected(error)));
+        }
+
+        // Reconnect after a delay if connection is lost
+        wait(stop, Duration::from_secs(1));
+    }
+}
+
+/// Connect to niri and request the event stream, registering the connection so it can be closed
+fn request_event_stream(stop: &AtomicBool, connection: &Mutex<Option<UnixStream>>) -> io::Result<BufReader<UnixStream>> {
+    let path = std::env::var_os(SOCKET_PATH_ENV).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not set, are you running this within niri?", SOCKET_PATH_ENV),
+        )
+    })?;
+    let stream = UnixStream::connect(path)?;
+    *connection.lock().unwrap_or_else(PoisonError::into_inner) = Some(stream.try_clone()?);
+    // Checked after registering, so a stop either sees this connection or is seen here
+    if stop.load(Ordering::SeqCst) {
+        return Err(io::Error::new(io::ErrorKind::Interrupted, "stopped"));
+    }
+
+    let mut reader = BufReader::new(stream);
+    let mut request = serde_json::to_string(&Request::EventStream)?;
+    request.push('\n');
+    reader.get_mut().write_all(request.as_bytes())?;
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let reply: Reply = serde_json::from_str(&line)?;
+    reply.map_err(io::Error::other)?;
+
+    let _ = reader.get_mut().shutdown(Shutdown::Write);
+    Ok(reader)
+}
+
+/// Sleep for `duration`, returning early when the stream is stopped
+fn wait(stop: &AtomicBool, duration: Duration) {
+    let deadline = Instant::now() + duration;
+    while !stop.load(Ordering::SeqCst) {
+        let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+            return;
+        };
+        thread::park_timeout(remaining);
+    }
+}
@@ -0,0 +1,514 @@
+//! Format string templates for workspace button labels.
+//!
+//! Templates are parsed once when the config is loaded:
+//!
+//! - `{field}` inserts a field, e.g. `{name}` or `{count}`
+//! - `{field:>5.10}` pads to 5 characters (`<` left, `>` right, `^` center) and truncates to 10
+//! - `{name|index}` uses the first field that has a value
+//! - `{?name}...{/name}` only renders its contents when `name` has a value
+//! - `{{` and `}}` insert literal braces
+//!
+//! A field has no value when it is unset, empty, or `0`.
+
+use std::collections::HashMap;
+use std::fmt;
+use waybar_cffi::gtk;
+use waybar_cffi::serde::de::{self, Deserializer, IgnoredAny, MapAccess, Visitor};
+use waybar_cffi::serde::Deserialize;
+
+/// A field that can be referenced from a format string
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Field {
+    Icon,
+    Value,
+    Name,
+    Index,
+    Output,
+    Count,
+    FocusedTitle,
+    FocusedAppId,
+    UrgentCount,
+    Bind,
+}
+
+impl Field {
+    const ALL: &'static [(&'static str, Field)] = &[
+        ("icon", Field::Icon),
+        ("value", Field::Value),
+        ("name", Field::Name),
+        ("index", Field::Index),
+        ("output", Field::Output),
+        ("count", Field::Count),
+        ("focused_title", Field::FocusedTitle),
+        ("focused_app_id", Field::FocusedAppId),
+        ("urgent_count", Field::UrgentCount),
+        ("bind", Field::Bind),
+    ];
+
+    fn from_name(name: &str) -> Option<Field> {
+        Self::ALL.iter().find(|(n, _)| *n == name).map(|(_, field)| *field)
+    }
+
+    /// Icons are already markup; every other field is escaped before insertion
+    fn is_markup(self) -> bool {
+        self == Field::Icon
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Align {
+    #[default]
+    Left,
+    Right,
+    Center,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Spec {
+    align: Align,
+    width: Option<usize>,
+    precision: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Field { fields: Vec<Field>, spec: Spec },
+    Conditional { field: Field, body: Vec<Node> },
+}
+
+/// A parsed format string
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "waybar_cffi::serde", try_from = "String")]
+pub(crate) struct Template {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ParseError {
+    message: String,
+    position: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid format string at character {}: {}", self.position, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl TryFrom<String> for Template {
+    type Error = ParseError;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        Template::parse(&source)
+    }
+}
+
+impl Template {
+    pub(crate) fn parse(source: &str) -> Result<Template, ParseError> {
+        let mut parser = Parser { chars: source.chars().collect(), pos: 0 };
+        let nodes = parser.parse_nodes(None)?;
+        Ok(Template { nodes })
+    }
+
+    /// Render the template, looking up raw (unescaped) field values with `lookup`
+    pub(crate) fn render(&self, lookup: impl Fn(Field) -> Option<String>) -> String {
+        let mut out = String::new();
+        render_nodes(&self.nodes, &lookup, &mut out);
+        out
+    }
+}
+
+/// State-specific `format-<state>` and `format-<name>` overrides
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StateFormats {
+    pub(crate) urgent: Option<Template>,
+    pub(crate) empty: Option<Template>,
+    pub(crate) focused: Option<Template>,
+    pub(crate) active: Option<Template>,
+    /// Keyed by workspace name or index
+    pub(crate) named: HashMap<String, Template>,
+}
+
+impl<'de> Deserialize<'de> for StateFormats {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct StateFormatsVisitor;
+
+        impl<'de> Visitor<'de> for StateFormatsVisitor {
+            type Value = StateFormats;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("format-<state> strings")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<StateFormats, A::Error> {
+                let mut formats = StateFormats::default();
+                while let Some(key) = map.next_key::<String>()? {
+                    // Only format-* keys are ours; everything else belongs to other options
+                    let Some(state) = key.strip_prefix("format-") else {
+                        map.next_value::<IgnoredAny>()?;
+                        continue;
+                    };
+                    let source: String = map.next_value()?;
+                    let template = Template::parse(&source)
+                        .map_err(|e| de::Error::custom(format!("{}: {}", key, e)))?;
+                    match state {
+                        "urgent" => formats.urgent = Some(template),
+                        "empty" => formats.empty = Some(template),
+                        "focused" => formats.focused = Some(template),
+                        "active" => formats.active = Some(template),
+                        _ => {
+                            formats.named.insert(state.to_string(), template);
+                        }
+                    }
+                }
+                Ok(formats)
+            }
+        }
+
+        deserializer.deserialize_map(StateFormatsVisitor)
+    }
+}
+
+fn has_value(value: &Option<String>) -> bool {
+    value.as_deref().is_some_and(|v| !v.is_empty() && v != "0")
+}
+
+fn render_nodes(nodes: &[Node], lookup: &impl Fn(Field) -> Option<String>, out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Field { fields, spec } => {
+                // Fall back through alternatives; the last one is used even without a value
+                let mut chosen = None;
+                for (i, field) in fields.iter().enumerate() {
+                    let value = lookup(*field);
+                    if has_value(&value) || i == fields.len() - 1 {
+                        chosen = Some((*field, value.unwrap_or_default()));
+                        break;
+                    }
+                }
+                if let Some((field, value)) = chosen {
+                    if field.is_markup() {
+                        out.push_str(&value);
+                    } else {
+                        let value = apply_spec(&value, spec);
+                        out.push_str(&gtk::glib::markup_escape_text(&value));
+                    }
+                }
+            }
+            Node::Conditional { field, body } => {
+                if has_value(&lookup(*field)) {
+                    render_nodes(body, lookup, out);
+                }
+            }
+        }
+    }
+}
+
+fn apply_spec(value: &str, spec: &Spec) -> String {
+    let mut value: String = match spec.precision {
+        Some(max) => value.chars().take(max).collect(),
+        None => value.to_string(),
+    };
+
+    if let Some(width) = spec.width {
+        let len = value.chars().count();
+        if len < width {
+            let pad = width - len;
+            let (left, right) = match spec.align {
+                Align::Left => (0, pad),
+                Align::Right => (pad, 0),
+                Align::Center => (pad / 2, pad - pad / 2),
+            };
+            value = format!("{}{}{}", " ".repeat(left), value, " ".repeat(right));
+        }
+    }
+
+    value
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn error(&self, position: usize, message: impl Into<String>) -> ParseError {
+        ParseError { message: message.into(), position }
+    }
+
+    /// Parse nodes until the end of input, or until `{/closing}` when inside a conditional
+    fn parse_nodes(&mut self, closing: Option<(Field, usize)>) -> Result<Vec<Node>, ParseError> {
+        let mut nodes = Vec::new();
+        let mut text = String::new();
+
+        while let Some(&c) = self.chars.get(self.pos) {
+            match c {
+                '{' if self.chars.get(self.pos + 1) == Some(&'{') => {
+                    text.push('{');
+                    self.pos += 2;
+                }
+                '}' if self.chars.get(self.pos + 1) == Some(&'}') => {
+                    text.push('}');
+                    self.pos += 2;
+                }
+                '}' => return Err(self.error(self.pos, "unmatched '}' (use '}}' for a literal brace)")),
+                '{' => {
+                    let start = self.pos;
+                    let inner = self.read_tag()?;
+                    if !text.is_empty() {
+                        nodes.push(Node::Text(std::mem::take(&mut text)));
+                    }
+
+                    if let Some(name) = inner.strip_prefix('/') {
+                        let field = self.field(name, start)?;
+                        return match closing {
+                            Some((open, _)) if open == field => Ok(nodes),
+                            _ => Err(self.error(start, format!("'{{/{}}}' has no matching '{{?{}}}'", name, name))),
+                        };
+                    } else if let Some(name) = inner.strip_prefix('?') {
+                        let field = self.field(name, start)?;
+                        let body = self.parse_nodes(Some((field, start)))?;
+                        nodes.push(Node::Conditional { field, body });
+                    } else {
+                        nodes.push(self.parse_field(&inner, start)?);
+                    }
+                }
+                _ => {
+                    text.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+
+        if let Some((field, start)) = closing {
+            let name = Field::ALL.iter().find(|(_, f)| *f == field).map_or("", |(n, _)| n);
+            return Err(self.error(start, format!("'{{?{}}}' is never closed with '{{/{}}}'", name, name)));
+        }
+        if !text.is_empty() {
+            nodes.push(Node::Text(text));
+        }
+        Ok(nodes)
+    }
+
+    /// Read the contents of a `{...}` tag, leaving the position after the closing brace
+    fn read_tag(&mut self) -> Result<String, ParseError> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut inner = String::new();
+        loop {
+            match self.chars.get(self.pos) {
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(inner);
+                }
+                Some('{') | None => return Err(self.error(start, "unclosed '{' (use '{{' for a literal brace)")),
+                Some(&c) => {
+                    inner.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    fn field(&self, name: &str, position: usize) -> Result<Field, ParseError> {
+        Field::from_name(name.trim()).ok_or_else(|| {
+            let known: Vec<_> = Field::ALL.iter().map(|(n, _)| *n).collect();
+            self.error(position, format!("unknown placeholder '{{{}}}' (expected one of: {})", name, known.join(", ")))
+        })
+    }
+
+    fn parse_field(&self, inner: &str, position: usize) -> Result<Node, ParseError> {
+        let (names, spec) = match inner.split_once(':') {
+            Some((names, spec)) => (names, self.parse_spec(spec, position)?),
+            None => (inner, Spec::default()),
+        };
+
+        let fields = names
+            .split('|')
+            .map(|name| self.field(name, position))
+            .collect::<Result<Vec<_>, _>>()?;
+        // Padding or truncating markup would count and cut its tags
+        if (spec.width.is_some() || spec.precision.is_some()) && fields.iter().any(|field| field.is_markup()) {
+            return Err(self.error(position, format!("'{{{}}}' can't take a width or precision, icons are markup", inner)));
+        }
+
+        Ok(Node::Field { fields, spec })
+    }
+
+    fn parse_spec(&self, spec: &str, position: usize) -> Result<Spec, ParseError> {
+        let invalid = || self.error(position, format!("invalid format spec ':{}' (expected e.g. ':>5' or ':.10')", spec));
+
+        let mut rest = spec;
+        let mut result = Spec::default();
+        if let Some(c) = rest.chars().next() {
+            let align = match c {
+                '<' => Some(Align::Left),
+                '>' => Some(Align::Right),
+                '^' => Some(Align::Center),
+                _ => None,
+            };
+            if let Some(align) = align {
+                result.align = align;
+                rest = &rest[1..];
+            }
+        }
+
+        let (width, precision) = match rest.split_once('.') {
+            Some((width, precision)) => (width, Some(precision)),
+            None => (rest, None),
+        };
+        if !width.is_empty() {
+            result.width = Some(width.parse().map_err(|_| invalid())?);
+        }
+        if let Some(precision) = precision {
+            result.precision = Some(precision.parse().map_err(|_| invalid())?);
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(source: &str, values: &[(Field, &str)]) -> String {
+        Template::parse(source)
+            .unwrap()
+            .render(|field| values.iter().find(|(f, _)| *f == field).map(|(_, v)| v.to_string()))
+    }
+
+    fn error_position(source: &str) -> usize {
+        Template::parse(source).unwrap_err().position
+    }
+
+    #[test]
+    fn text_and_fields() {
+        let values = [(Field::Name, "web"), (Field::Count, "3")];
+        assert_eq!(render("{name} ({count})", &values), "web (3)");
+        assert_eq!(render("no fields", &values), "no fields");
+        assert_eq!(render("{ name }", &values), "web");
+        assert_eq!(render("{output}", &values), "");
+    }
+
+    #[test]
+    fn width_and_alignment() {
+        let values = [(Field::Name, "ab")];
+        assert_eq!(render("{name:5}|", &values), "ab   |");
+        assert_eq!(render("{name:<5}|", &values), "ab   |");
+        assert_eq!(render("{name:>5}|", &values), "   ab|");
+        assert_eq!(render("{name:^5}|", &values), " ab  |");
+        assert_eq!(render("{name:1}|", &values), "ab|");
+    }
+
+    #[test]
+    fn truncation() {
+        let values = [(Field::Name, "abcdef")];
+        assert_eq!(render("{name:.3}", &values), "abc");
+        assert_eq!(render("{name:>5.3}", &values), "  abc");
+        assert_eq!(render("{name:.10}", &values), "abcdef");
+        // Counted in characters, not bytes
+        assert_eq!(render("{name:.2}", &[(Field::Name, "äöü")]), "äö");
+    }
+
+    #[test]
+    fn fallbacks() {
+        assert_eq!(render("{name|index}", &[(Field::Index, "2")]), "2");
+        assert_eq!(render("{name|index}", &[(Field::Name, ""), (Field::Index, "2")]), "2");
+        assert_eq!(render("{name|index}", &[(Field::Name, "web"), (Field::Index, "2")]), "web");
+        assert_eq!(render("{urgent_count|count}", &[(Field::UrgentCount, "0"), (Field::Count, "4")]), "4");
+        // The last alternative is used even without a value
+        assert_eq!(render("{name|count}", &[(Field::Count, "0")]), "0");
+        assert_eq!(render("[{name|output}]", &[]), "[]");
+    }
+
+    #[test]
+    fn conditionals() {
+        let source = "{index}{?name}: {name}{/name}";
+        assert_eq!(render(source, &[(Field::Index, "1"), (Field::Name, "web")]), "1: web");
+        assert_eq!(render(source, &[(Field::Index, "1")]), "1");
+        assert_eq!(render(source, &[(Field::Index, "1"), (Field::Name, "")]), "1");
+
+        let nested = "{?count}{count}{?urgent_count}!{/urgent_count}{/count}";
+        assert_eq!(render(nested, &[(Field::Count, "2"), (Field::UrgentCount, "1")]), "2!");
+        assert_eq!(render(nested, &[(Field::Count, "2"), (Field::UrgentCount, "0")]), "2");
+        assert_eq!(render(nested, &[(Field::Count, "0"), (Field::UrgentCount, "1")]), "");
+    }
+
+    #[test]
+    fn brace_escapes() {
+        assert_eq!(render("{{name}}", &[(Field::Name, "web")]), "{name}");
+        assert_eq!(render("{{{name}}}", &[(Field::Name, "web")]), "{web}");
+        assert_eq!(render("}}{{", &[]), "}{");
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(error_position("ab}"), 2);
+        assert_eq!(error_position("ab{name"), 2);
+        assert_eq!(error_position("{na{me}"), 0);
+        assert_eq!(error_position("x {foo}"), 2);
+        assert_eq!(error_position("{name|foo}"), 0);
+        assert_eq!(error_position("{name:x}"), 0);
+        assert_eq!(error_position("{name:.x}"), 0);
+        assert_eq!(error_position("ab {icon:.1}"), 3);
+        assert_eq!(error_position("{name} {name|icon:>3}"), 7);
+        assert_eq!(error_position("a{?name}b"), 1);
+        assert_eq!(error_position("ab{/name}"), 2);
+        assert_eq!(error_position("{?name}x{/count}"), 8);
+        assert_eq!(error_position("äö}"), 2);
+    }
+
+    #[test]
+    fn error_messages() {
+        let error = Template::parse("{foo}").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid format string at character 0: unknown placeholder '{foo}' (expected one of: \
+             icon, value, name, index, output, count, focused_title, focused_app_id, urgent_count, bind)"
+        );
+    }
+
+    #[test]
+    fn icon_takes_no_width_or_precision() {
+        let error = Template::parse("{icon:5}").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid format string at character 0: '{icon:5}' can't take a width or precision, icons are markup"
+        );
+        assert!(Template::parse("{name|icon:.5}").is_err());
+        assert!(Template::parse("{icon:^}").is_ok());
+    }
+
+    #[test]
+    fn fields_are_escaped_except_icon() {
+        assert_eq!(render("{name}", &[(Field::Name, "<b>&\"")]), "&lt;b&gt;&amp;&quot;");
+        assert_eq!(render("{focused_title}", &[(Field::FocusedTitle, "a < b")]), "a &lt; b");
+        assert_eq!(
+            render("{icon} {value}", &[(Field::Icon, "<span color='red'>x</span>"), (Field::Value, "<i>")]),
+            "<span color='red'>x</span> &lt;i&gt;"
+        );
+        // Truncation counts the raw text, before escaping
+        assert_eq!(render("{name:.2}", &[(Field::Name, "<b>")]), "&lt;b");
+        // Literal text is markup written by the user
+        assert_eq!(render("<b>{name}</b>", &[(Field::Name, "&")]), "<b>&amp;</b>");
+    }
+
+    #[test]
+    fn state_formats() {
+        let formats: StateFormats = serde_json::from_str(
+            r#"{"format-focused": "[{name}]", "format-web": "{icon}", "all-outputs": true}"#,
+        )
+        .unwrap();
+        assert!(formats.focused.is_some());
+        assert!(formats.urgent.is_none());
+        assert_eq!(formats.named.keys().collect::<Vec<_>>(), ["web"]);
+
+        let error = serde_json::from_str::<StateFormats>(r#"{"format-empty": "{nope}"}"#).unwrap_err();
+        assert!(error.to_string().starts_with("format-empty: invalid format string at character 0"));
+    }
+}
@@ -1,13 +1,27 @@
+mod binds;
+mod dbus;
+mod events;
+mod format;
+mod schema;
+mod validate;
+
+use binds::Binds;
+use events::{ConnectionStatus, ModuleEvent, Snapshot};
+use format::{Field, StateFormats, Template};
 use gtk::prelude::*;
 use niri_ipc::socket::Socket;
-use niri_ipc::{Action, Event, Request, Response, WorkspaceReferenceArg};
+use niri_ipc::{Action, Request, Response, WorkspaceReferenceArg};
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
-use std::thread;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use waybar_cffi::serde::Deserialize;
 use waybar_cffi::{gtk, waybar_module, InitInfo, Module};
 
 #[derive(Debug, Clone, Deserialize)]
-#[serde(crate = "waybar_cffi::serde")]
+#[serde(crate = "waybar_cffi::serde", rename_all = "kebab-case")]
 struct IgnoreRule {
     app_id: Option<String>,
     title: Option<String>,
@@ -30,603 +44,1305 @@ struct FormatIcons {
     named: HashMap<String, String>,
 }
 
+/// Which empty workspaces get a button
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(crate = "waybar_cffi::serde", rename_all = "snake_case")]
+enum WorkspaceVisibility {
+    /// Every workspace
+    All,
+    /// Only workspaces with windows
+    Occupied,
+    /// Occupied workspaces plus the focused (or active, per output) one
+    OccupiedFocused,
+    /// Occupied workspaces plus the next empty one after them
+    #[default]
+    OccupiedTrailing,
+}
+
+/// Which windows decide whether a workspace is empty
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(crate = "waybar_cffi::serde", rename_all = "snake_case")]
+enum EmptyDetection {
+    /// No windows left after ignore_rules
+    #[default]
+    Filtered,
+    /// No windows at all, ignored ones included
+    AllWindows,
+    /// No active window reported by niri
+    ActiveWindow,
+}
+
+/// When state from the event stream is redrawn
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(crate = "waybar_cffi::serde", rename_all = "snake_case")]
+enum RenderCoalescing {
+    /// Once per frame, however many updates arrived since the last one
+    #[default]
+    Frame,
+    /// Once the main loop is idle
+    Idle,
+    /// On every update
+    None,
+}
+
+/// How workspace buttons appear and disappear
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(crate = "waybar_cffi::serde", rename_all = "snake_case")]
+enum WorkspaceTransition {
+    #[default]
+    None,
+    /// Grow and shrink along the bar
+    Slide,
+    Crossfade,
+}
+
+impl WorkspaceTransition {
+    fn revealer_type(self) -> Option<gtk::RevealerTransitionType> {
+        match self {
+            Self::None => None,
+            // The workspace container is always horizontal
+            Self::Slide => Some(gtk::RevealerTransitionType::SlideRight),
+            Self::Crossfade => Some(gtk::RevealerTransitionType::Crossfade),
+        }
+    }
+}
+
+/// Entry in the format-icons priority list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(crate = "waybar_cffi::serde", rename_all = "lowercase")]
+enum IconKey {
+    Urgent,
+    Empty,
+    Focused,
+    Active,
+    Named,
+    Indexed,
+    Default,
+}
+
+impl IconKey {
+    fn name(self) -> &'static str {
+        match self {
+            IconKey::Urgent => "urgent",
+            IconKey::Empty => "empty",
+            IconKey::Focused => "focused",
+            IconKey::Active => "active",
+            IconKey::Named => "named",
+            IconKey::Indexed => "indexed",
+            IconKey::Default => "default",
+        }
+    }
+
+    /// State keys describe the workspace; the rest identify it
+    fn is_state(self) -> bool {
+        matches!(self, IconKey::Urgent | IconKey::Empty | IconKey::Focused | IconKey::Active)
+    }
+
+    fn applies(self, ws: &niri_ipc::Workspace, is_empty: bool) -> bool {
+        match self {
+            IconKey::Urgent => ws.is_urgent,
+            IconKey::Empty => is_empty,
+            IconKey::Focused => ws.is_focused,
+            IconKey::Active => ws.is_active,
+            IconKey::Named | IconKey::Indexed | IconKey::Default => true,
+        }
+    }
+}
+
+/// Glyph sequence used for the window count icon
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "waybar_cffi::serde", untagged)]
+enum PieGlyphs {
+    Preset(GlyphPreset),
+    Custom(Vec<String>),
+}
+
+impl Default for PieGlyphs {
+    fn default() -> Self {
+        PieGlyphs::Preset(GlyphPreset::Hexagon)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(crate = "waybar_cffi::serde", rename_all = "lowercase")]
+enum GlyphPreset {
+    Hexagon,
+    Circle,
+    Square,
+    Dots,
+    Numeric,
+}
+
+const HEXAGON_GLYPHS: &[&str] = &["󰋙", "󰫃", "󰫄", "󰫅", "󰫆", "󰫇", "󰫈"];
+const CIRCLE_GLYPHS: &[&str] = &["󰝦", "󰪞", "󰪟", "󰪠", "󰪡", "󰪢", "󰪣", "󰪤", "󰪥"];
+const SQUARE_GLYPHS: &[&str] = &["□", "▖", "▌", "▙", "█"];
+const DOTS_GLYPHS: &[&str] = &["·", "⠁", "⠃", "⠇", "⡇", "⡏", "⡟", "⡿", "⣿"];
+
+impl PieGlyphs {
+    /// Glyph for the given window count; counts past the end of the sequence reuse the last glyph
+    fn glyph(&self, count: usize) -> String {
+        let preset = match self {
+            PieGlyphs::Preset(GlyphPreset::Hexagon) => HEXAGON_GLYPHS,
+            PieGlyphs::Preset(GlyphPreset::Circle) => CIRCLE_GLYPHS,
+            PieGlyphs::Preset(GlyphPreset::Square) => SQUARE_GLYPHS,
+            PieGlyphs::Preset(GlyphPreset::Dots) => DOTS_GLYPHS,
+            PieGlyphs::Preset(GlyphPreset::Numeric) => return count.to_string(),
+            PieGlyphs::Custom(glyphs) => {
+                return glyphs
+                    .get(count)
+                    .or(glyphs.last())
+                    .cloned()
+                    .unwrap_or_else(|| count.to_string());
+            }
+        };
+        preset[count.min(preset.len() - 1)].to_string()
+    }
+
+    /// Nerd Font pie glyphs sit slightly off-center and need a trailing thin space
+    fn needs_padding(&self) -> bool {
+        matches!(self, PieGlyphs::Preset(GlyphPreset::Hexagon | GlyphPreset::Circle))
+    }
+}
+
+/// How the window count icon is rendered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(crate = "waybar_cffi::serde", rename_all = "lowercase")]
+enum PieMode {
+    #[default]
+    Glyph,
+    Pie,
+    Ring,
+}
+
+/// Shape of the per-window indicators
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(crate = "waybar_cffi::serde", rename_all = "lowercase")]
+enum IndicatorStyle {
+    Dots,
+    Bars,
+}
+
+/// App ids of the windows on a workspace, attached to its pie drawing area
+struct PieApps(Vec<Option<String>>);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "waybar_cffi::serde", rename_all = "kebab-case")]
+struct TitleRewrite {
+    /// Exact app ID the rule applies to
+    app_id: Option<String>,
+    /// Title pattern where `*` matches anything; matched parts are available as `$1`, `$2`, ...
+    title: Option<String>,
+    /// Replacement text; `$0` is the whole original title
+    replace: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "waybar_cffi::serde", rename_all = "kebab-case")]
+struct WindowTitle {
+    #[serde(default = "default_window_title_format")]
+    format: Template,
+    #[serde(default)]
+    max_length: Option<i32>,
+    #[serde(default)]
+    rewrite: Vec<TitleRewrite>,
+}
+
 struct NiriWorkspaces {
+    workspaces: Workspaces,
+    /// Stops event handling and removes the stylesheet when Waybar unloads the module
+    _shutdown: Shutdown,
+}
+
+/// Widgets and state shared with the event, timer and file monitor callbacks
+#[derive(Clone)]
+struct Workspaces {
     container: gtk::Box,
     config: Config,
+    title_label: Option<gtk::Label>,
+    urgent_indicator: Option<gtk::Button>,
+    /// Workspace id and timeout of a pending urgent_auto_focus
+    pending_urgent_focus: Rc<RefCell<Option<(u64, gtk::glib::SourceId)>>>,
+    peek_state: Rc<RefCell<PeekState>>,
+    /// `focus-workspace` binds from niri's config, for `{bind}` and tooltips
+    binds: Rc<RefCell<Binds>>,
+    /// Watches the config files the binds came from
+    binds_monitors: Rc<RefCell<Vec<gtk::gio::FileMonitor>>>,
+    /// Latest state from the event stream, rendered on the next frame
+    pending_state: Rc<RefCell<Option<Arc<Snapshot>>>>,
+    /// Idle or timeout source that will render `pending_state`
+    render_source: Rc<RefCell<Option<gtk::glib::SourceId>>>,
+    metrics: Rc<RefCell<RenderMetrics>>,
+    /// `current_only`, toggled over D-Bus
+    current_only: Rc<Cell<bool>>,
+    /// The workspaces as last rendered, for `GetWorkspaces`
+    view: Rc<RefCell<Vec<serde_json::Value>>>,
+    /// Output this bar shows workspaces for, as last rendered; `None` with `all_outputs`
+    bar_output: Rc<RefCell<Option<String>>>,
+    dbus: Rc<RefCell<Option<dbus::Service>>>,
 }
 
-impl NiriWorkspaces {
+/// Counters for checking how well updates are coalesced
+#[derive(Default)]
+struct RenderMetrics {
+    /// niri events that changed workspaces or windows
+    events: u64,
+    /// Snapshots received from the event thread, one per burst of events
+    snapshots: u64,
+    /// Full queries to niri, on start, reload and Waybar's `update`
+    queries: u64,
+    renders: u64,
+    render_time: Duration,
+    max_render_time: Duration,
+    last_render: Option<Instant>,
+}
+
+impl RenderMetrics {
+    fn record_render(&mut self, started: Instant) {
+        let elapsed = started.elapsed();
+        self.renders += 1;
+        self.render_time += elapsed;
+        self.max_render_time = self.max_render_time.max(elapsed);
+        self.last_render = Some(started);
+    }
+}
+
+impl fmt::Display for RenderMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let average = self.render_time.checked_div(self.renders as u32).unwrap_or_default();
+        write!(
+            f,
+            "{} events in {} snapshots, {} queries, {} renders ({} coalesced); render avg {:.2} ms, max {:.2} ms",
+            self.events,
+            self.snapshots,
+            self.queries,
+            self.renders,
+            (self.snapshots + self.queries).saturating_sub(self.renders),
+            average.as_secs_f64() * 1000.0,
+            self.max_render_time.as_secs_f64() * 1000.0,
+        )
+    }
+}
+
+/// Everything the module registered outside its own widgets, undone on drop
+struct Shutdown {
+    /// Dropping the last subscription stops the event thread and closes its socket
+    _subscription: events::Subscription,
+    css_provider: gtk::CssProvider,
+    blink_timeout: Option<gtk::glib::SourceId>,
+    metrics_timeout: Option<gtk::glib::SourceId>,
+    /// One-shot sources armed while the module runs, shared with `Workspaces`
+    pending_urgent_focus: Rc<RefCell<Option<(u64, gtk::glib::SourceId)>>>,
+    peek_state: Rc<RefCell<PeekState>>,
+    render_source: Rc<RefCell<Option<gtk::glib::SourceId>>>,
+    binds_monitors: Rc<RefCell<Vec<gtk::gio::FileMonitor>>>,
+    /// Unregistered on drop; its method handler holds the module's state
+    dbus: Rc<RefCell<Option<dbus::Service>>>,
+}
+
+impl Drop for Shutdown {
+    fn drop(&mut self) {
+        if let Some(screen) = gtk::gdk::Screen::default() {
+            gtk::StyleContext::remove_provider_for_screen(&screen, &self.css_provider);
+        }
+        if let Some(timeout) = self.blink_timeout.take() {
+            timeout.remove();
+        }
+        if let Some(timeout) = self.metrics_timeout.take() {
+            timeout.remove();
+        }
+        if let Some((_, timeout)) = self.pending_urgent_focus.take() {
+            timeout.remove();
+        }
+        if let Some(timeout) = self.peek_state.borrow_mut().restore_timeout.take() {
+            timeout.remove();
+        }
+        if let Some(source) = self.render_source.take() {
+            source.remove();
+        }
+        for monitor in self.binds_monitors.take() {
+            monitor.cancel();
+        }
+        drop(self.dbus.take());
+    }
+}
+
+/// Shared by all buttons so moving between them keeps the original workspace
+#[derive(Default)]
+struct PeekState {
+    /// Workspace niri last reported focused, on any output
+    focused: Option<u64>,
+    /// Workspace focused before peeking started, restored on leave
+    origin: Option<u64>,
+    /// Pending restore, cancelled when the pointer enters another button
+    restore_timeout: Option<gtk::glib::SourceId>,
+}
+
+#[derive(Clone)]
+struct HoverPeek {
+    delay: u64,
+    state: Rc<RefCell<PeekState>>,
+}
+
+/// Longest wait for a frame before rendering anyway, in case the bar is hidden in the meantime
+const FRAME_FALLBACK_MS: u64 = 100;
+
+/// Grace period before restoring after leaving a button, so moving to a neighbour doesn't flicker
+const PEEK_RESTORE_DELAY_MS: u64 = 150;
+
+impl Workspaces {
+    /// Query niri for the current state and render it
     fn populate_workspaces(&self) {
-        // Get workspace and window information
-        let (workspaces, window_counts) = match get_workspace_info(&self.config.ignore_rules) {
-            Ok(data) => data,
-            Err(e) => {
-                eprintln!("Failed to get workspace info: {}", e);
+        self.metrics.borrow_mut().queries += 1;
+        match get_workspace_info(&self.config.ignore_rules) {
+            Ok(info) => {
+                let started = Instant::now();
+                self.render(&info);
+                self.metrics.borrow_mut().record_render(started);
+            }
+            Err(e) => eprintln!("Failed to get workspace info: {}", e),
+        }
+    }
+
+    /// Render state from the event stream as `render_coalescing` and `max_render_rate` allow,
+    /// so a burst of updates draws once
+    fn queue_render(&self, snapshot: Arc<Snapshot>) {
+        {
+            let mut metrics = self.metrics.borrow_mut();
+            metrics.events += snapshot.events as u64;
+            metrics.snapshots += 1;
+        }
+        let already_queued = self.pending_state.borrow_mut().replace(snapshot).is_some();
+        if already_queued {
+            return;
+        }
+
+        // Wait out the rest of the minimum interval first; later updates replace the pending state
+        let min_interval = self
+            .config
+            .max_render_rate
+            .filter(|rate| *rate > 0)
+            .map(|rate| Duration::from_secs(1) / rate);
+        let last_render = self.metrics.borrow().last_render;
+        if let (Some(min_interval), Some(last_render)) = (min_interval, last_render) {
+            if let Some(remaining) = min_interval.checked_sub(last_render.elapsed()).filter(|d| !d.is_zero()) {
+                let workspaces = self.clone();
+                let source_id = gtk::glib::timeout_add_local_once(remaining, move || {
+                    workspaces.render_source.take();
+                    workspaces.render_pending();
+                });
+                *self.render_source.borrow_mut() = Some(source_id);
                 return;
             }
+        }
+
+        let workspaces = self.clone();
+        match self.config.render_coalescing {
+            RenderCoalescing::Frame => {
+                // Hidden bars have no frame clock, so fall back to a timer until the bar is shown again
+                let fallback = if self.container.is_mapped() {
+                    self.container.add_tick_callback(move |_, _| {
+                        workspaces.render_pending();
+                        gtk::glib::ControlFlow::Break
+                    });
+                    Duration::from_millis(FRAME_FALLBACK_MS)
+                } else {
+                    Duration::ZERO
+                };
+                let workspaces = self.clone();
+                let source_id = gtk::glib::timeout_add_local_once(fallback, move || {
+                    workspaces.render_source.take();
+                    workspaces.render_pending();
+                });
+                *self.render_source.borrow_mut() = Some(source_id);
+            }
+            RenderCoalescing::Idle => {
+                let source_id = gtk::glib::idle_add_local_once(move || {
+                    workspaces.render_source.take();
+                    workspaces.render_pending();
+                });
+                *self.render_source.borrow_mut() = Some(source_id);
+            }
+            RenderCoalescing::None => workspaces.render_pending(),
+        }
+    }
+
+    /// Render the latest state queued by `queue_render`, if it's still pending
+    fn render_pending(&self) {
+        let Some(snapshot) = self.pending_state.borrow_mut().take() else {
+            return;
         };
+        // Rendered by the frame clock before the fallback timer fired
+        if let Some(source) = self.render_source.take() {
+            source.remove();
+        }
+        let started = Instant::now();
+        let info = WorkspaceInfo::new(snapshot.workspaces.clone(), snapshot.windows.clone(), &self.config.ignore_rules);
+        self.render(&info);
+        self.metrics.borrow_mut().record_render(started);
+    }
 
+    fn set_connection_status(&self, status: ConnectionStatus) {
+        if let ConnectionStatus::Disconnected(e) = &status {
+            eprintln!("niri event stream disconnected: {}", e);
+        }
+        Self::update_css_class(
+            &self.container.style_context(),
+            "disconnected",
+            status != ConnectionStatus::Connected,
+        );
+    }
+
+    fn render(&self, info: &WorkspaceInfo) {
         // Get output name from the first workspace (if any)
         let output_name = if self.config.all_outputs {
             None // Don't filter by output
         } else {
-            workspaces
+            info.workspaces
                 .first()
                 .and_then(|ws| ws.output.clone())
         };
 
-        // Find the highest workspace index with windows on this output (or all outputs)
-        let max_workspace_idx = workspaces
-            .iter()
-            .filter(|ws| {
-                if self.config.all_outputs {
-                    window_counts.get(&ws.id).copied().unwrap_or(0) > 0
-                } else {
-                    ws.output == output_name && window_counts.get(&ws.id).copied().unwrap_or(0) > 0
-                }
-            })
-            .map(|ws| ws.idx)
-            .max()
-            .unwrap_or(0);
+        // Find the highest workspace index with windows on each output
+        let mut max_workspace_idx: HashMap<Option<String>, u8> = HashMap::new();
+        for ws in info.workspaces.iter().filter(|ws| !self.config.workspace_is_empty(ws, info)) {
+            let max = max_workspace_idx.entry(ws.output.clone()).or_insert(0);
+            *max = (*max).max(ws.idx);
+        }
 
-        // Filter and sort workspaces
-        let mut our_workspaces: Vec<_> = workspaces
-            .into_iter()
+        // Filter workspaces by output and visibility policy
+        let mut our_workspaces: Vec<_> = info.workspaces
+            .iter()
             .filter(|ws| {
                 // Filter by output unless all_outputs is enabled
                 if !self.config.all_outputs && ws.output != output_name {
                     return false;
                 }
 
-                let has_windows = window_counts.get(&ws.id).copied().unwrap_or(0) > 0;
-
-                // Show workspaces with windows, or the next empty workspace based on config
-                if has_windows {
-                    true
-                } else if self.config.show_empty_workspace {
-                    ws.idx == max_workspace_idx + 1  // Always show next empty workspace
-                } else {
-                    ws.idx == max_workspace_idx + 1 && ws.is_active  // Only show when active
-                }
+                let max_idx = max_workspace_idx.get(&ws.output).copied().unwrap_or(0);
+                self.is_workspace_visible(ws, info, max_idx)
             })
+            .cloned()
             .collect();
 
-        // Sort by workspace index to maintain consistent order
-        our_workspaces.sort_by_key(|ws| ws.idx);
-
-        // Get existing buttons
-        let existing_buttons = self.container.children();
-
-        // Check if we need to rebuild: count changed OR workspace IDs changed
-        let need_rebuild = existing_buttons.len() != our_workspaces.len() || {
-            existing_buttons.iter()
-                .zip(our_workspaces.iter())
-                .any(|(button, ws)| {
-                    button.downcast_ref::<gtk::Button>()
-                        .and_then(|b| unsafe { b.data::<u64>("ws_id").map(|ptr| *ptr.as_ptr()) })
-                        .map_or(true, |stored_id| stored_id != ws.id)
-                })
-        };
-
-        if need_rebuild {
-            // Clear existing buttons
-            for child in existing_buttons {
-                self.container.remove(&child);
-            }
-
-            // Create new buttons for each workspace
-            for ws in &our_workspaces {
-                let button = gtk::Button::new();
-                // Add CSS class for styling
-                button.style_context().add_class("workspace-button");
-                // Enable markup for colored icons and center align
-                if let Some(label) = button.child().and_then(|w| w.downcast::<gtk::Label>().ok()) {
-                    label.set_use_markup(true);
-                    label.set_xalign(0.5);
-                    label.set_yalign(0.5);
-                    label.set_halign(gtk::Align::Center);
-                    label.set_valign(gtk::Align::Center);
+        // Fill in missing indices up to min_workspaces with placeholder buttons
+        if self.config.min_workspaces > 0 {
+            let mut outputs: Vec<_> = if self.config.all_outputs {
+                info.workspaces.iter().map(|ws| ws.output.clone()).collect()
+            } else {
+                vec![output_name.clone()]
+            };
+            outputs.sort();
+            outputs.dedup();
+
+            for output in outputs {
+                for idx in 1..=self.config.min_workspaces {
+                    let exists = our_workspaces.iter().any(|ws| ws.output == output && ws.idx == idx);
+                    if !exists {
+                        our_workspaces.push(placeholder_workspace(output.clone(), idx, None));
+                    }
                 }
+            }
+        }
 
-                // Store workspace index for drag-and-drop
-                unsafe { button.set_data("ws_idx", ws.idx); }
-
-                // Set up drag-and-drop for workspace reordering
-                setup_workspace_drag_drop(&button, ws.id, self.config.drag_hover_focus, self.config.drag_hover_focus_delay as u64);
-
-                self.container.add(&button);
+        // Declared persistent workspaces are always shown, created as placeholders when missing
+        let persistent = self.config.persistent_names(output_name.as_deref());
+        for (name, output) in &persistent {
+            if our_workspaces.iter().any(|ws| ws.name.as_ref() == Some(name)) {
+                continue;
+            }
+            match info.workspaces.iter().find(|ws| ws.name.as_ref() == Some(name)) {
+                Some(ws) if self.config.all_outputs || ws.output == output_name => our_workspaces.push(ws.clone()),
+                Some(_) => {}  // Lives on another output's bar
+                None => our_workspaces.push(placeholder_workspace(output.clone(), 0, Some(name.clone()))),
             }
         }
 
+        // Persistent workspaces come first in declared order, the rest by index
+        our_workspaces.sort_by_key(|ws| {
+            let rank = ws.name.as_ref()
+                .and_then(|name| persistent.iter().position(|(n, _)| n == name))
+                .unwrap_or(usize::MAX);
+            (rank, ws.idx)
+        });
+
+        let buttons = self.reconcile_buttons(&our_workspaces);
+        self.peek_state.borrow_mut().focused = info.workspaces.iter().find(|ws| ws.is_focused).map(|ws| ws.id);
+        let mut view = Vec::new();
+
         // Update all buttons with current state
-        let buttons = self.container.children();
         for (button, ws) in buttons.iter().zip(our_workspaces.iter()) {
-            if let Some(button) = button.downcast_ref::<gtk::Button>() {
-                let window_count = window_counts.get(&ws.id).copied().unwrap_or(0);
+            let window_count = info.window_count(ws.id);
+            let is_empty = self.config.workspace_is_empty(ws, info);
+            let urgent_count = info.urgent_count(ws.id);
 
-                // Determine the display value
-                let value = if let Some(name) = &ws.name {
-                    name.clone()
-                } else {
-                    ws.idx.to_string()
-                };
+            // Determine the display value
+            let value = if let Some(name) = &ws.name {
+                name.clone()
+            } else {
+                ws.idx.to_string()
+            };
 
-                // Get the icon (either from format-icons or pie chart)
-                let icon = if self.config.format_icons.is_some() {
-                    get_format_icon(ws, self.config.format_icons.as_ref(), &value)
-                } else {
-                    get_pie_icon(window_count, self.config.icon_size.as_deref())
-                };
+            // Get the icon (either from format-icons or pie chart)
+            let content = get_button_content(button);
+            let icon = if self.config.format_icons.is_some() {
+                get_format_icon(ws, is_empty, &self.config, &value)
+            } else if content.is_some() {
+                String::new()  // Shown by the pie drawing area or window indicators instead
+            } else {
+                get_pie_icon(window_count, &self.config)
+            };
 
-                // Build the label using format string or default to icon
-                // Escape user-controlled data to prevent markup injection
-                let escaped_value = gtk::glib::markup_escape_text(&value);
-                let escaped_name = gtk::glib::markup_escape_text(ws.name.as_deref().unwrap_or(""));
-                let escaped_index = gtk::glib::markup_escape_text(&ws.idx.to_string());
-                let escaped_output = gtk::glib::markup_escape_text(ws.output.as_deref().unwrap_or(""));
-
-                let label_text = if let Some(format) = &self.config.format {
-                    format
-                        .replace("{icon}", &icon)  // Icon is safe (hardcoded markup or user-provided)
-                        .replace("{value}", &escaped_value)
-                        .replace("{name}", &escaped_name)
-                        .replace("{index}", &escaped_index)
-                        .replace("{output}", &escaped_output)
-                } else {
-                    icon
-                };
+            let bind = self.binds.borrow().get(ws.name.as_deref(), ws.idx).map(str::to_string);
+
+            let windows = info.windows(ws.id);
+            let active_window = windows.iter().find(|w| Some(w.id) == ws.active_window_id);
+
+            // Build the label using format string or default to icon
+            // The template escapes user-controlled data to prevent markup injection
+            let label_text = if let Some(format) = get_format(ws, is_empty, &self.config) {
+                format.render(|field| match field {
+                    Field::Icon => Some(icon.clone()),  // Icon is safe (hardcoded markup or user-provided)
+                    Field::Value => Some(value.clone()),
+                    Field::Name => ws.name.clone(),
+                    Field::Index => Some(ws.idx.to_string()),
+                    Field::Output => ws.output.clone(),
+                    Field::Count => Some(window_count.to_string()),
+                    Field::FocusedTitle => active_window.map(|w| self.config.rewrite_title(w)),
+                    Field::FocusedAppId => active_window.and_then(|w| w.app_id.clone()),
+                    Field::UrgentCount => Some(urgent_count.to_string()),
+                    Field::Bind => bind.clone(),
+                })
+            } else {
+                icon
+            };
 
-                // Update button label - always use markup for pie chart icons
+            // Update button label - always use markup for pie chart icons
+            if let Some((icon_widget, label)) = content {
+                if let Some(area) = icon_widget.downcast_ref::<gtk::DrawingArea>() {
+                    let apps = windows.iter().map(|w| w.app_id.clone()).collect();
+                    unsafe { area.set_data("pie_apps", PieApps(apps)); }
+                    area.queue_draw();
+                } else if let Some(indicators) = icon_widget.downcast_ref::<gtk::Box>() {
+                    update_window_indicators(indicators, windows, self.config.window_indicators_max);
+                }
+
+                label.set_markup(&label_text);
+                label.set_visible(!label_text.is_empty());
+            } else {
                 button.set_label(&label_text);
                 if let Some(label) = button.child().and_then(|w| w.downcast::<gtk::Label>().ok()) {
                     label.set_use_markup(true);
                 }
+            }
 
-                // Set button name for CSS targeting
-                button.set_widget_name(&format!("niri-workspace-{}", value));
+            button.set_tooltip_text(bind.as_deref());
 
-                let style_context = button.style_context();
+            // Set button name for CSS targeting
+            button.set_widget_name(&format!("niri-workspace-{}", value));
 
-                // Update CSS classes based on workspace state
-                Self::update_css_class(&style_context, "focused", ws.is_focused);
-                Self::update_css_class(&style_context, "active", ws.is_active);
-                Self::update_css_class(&style_context, "urgent", ws.is_urgent);
-                Self::update_css_class(&style_context, "empty", ws.active_window_id.is_none());
+            // Store workspace index for drag-and-drop and keyboard reordering
+            unsafe { button.set_data("ws_idx", ws.idx); }
 
-                // Add current_output class if workspace is on the same output as the bar
-                if let Some(ref bar_output) = output_name {
-                    Self::update_css_class(&style_context, "current_output",
-                        ws.output.as_ref() == Some(bar_output));
+            // Give screen readers a readable name instead of the icon glyphs
+            if let Some(accessible) = button.accessible() {
+                accessible.set_name(&get_accessible_name(ws, window_count));
+                accessible.set_description(&get_accessible_state(ws, is_empty));
+            }
+
+            let style_context = button.style_context();
+
+            // Update CSS classes based on workspace state
+            Self::update_css_class(&style_context, "focused", ws.is_focused);
+            Self::update_css_class(&style_context, "active", ws.is_active);
+            Self::update_css_class(&style_context, "urgent", ws.is_urgent || urgent_count > 0);
+            Self::update_css_class(&style_context, "empty", is_empty);
+            Self::update_css_class(&style_context, "placeholder", is_placeholder(ws.id));
+
+            // Expose the window count as a class when pie colors are left to CSS
+            let count_class = self.config.pie_css_classes
+                .then(|| format!("count-{}", pie_class_count(window_count, &self.config)));
+            for class in style_context.list_classes() {
+                if class.starts_with("count-") && Some(class.as_str()) != count_class.as_deref() {
+                    style_context.remove_class(&class);
                 }
+            }
+            if let Some(class) = count_class {
+                Self::update_css_class(&style_context, &class, true);
+            }
+            Self::update_css_class(&style_context, "crowded",
+                self.config.pie_css_classes && is_crowded(window_count, &self.config));
 
-                // Handle current_only visibility
-                if self.config.current_only {
-                    if self.config.all_outputs {
-                        button.set_visible(ws.is_focused);
-                    } else {
-                        button.set_visible(ws.is_active);
-                    }
+            // Add current_output class if workspace is on the same output as the bar
+            if let Some(ref bar_output) = output_name {
+                Self::update_css_class(&style_context, "current_output",
+                    ws.output.as_ref() == Some(bar_output));
+            }
+
+            // Handle current_only visibility
+            let visible = if self.current_only.get() {
+                if self.config.all_outputs {
+                    ws.is_focused
                 } else {
-                    button.set_visible(true);
+                    ws.is_active
                 }
+            } else {
+                true
+            };
+            button.set_visible(visible);
+
+            view.push(serde_json::json!({
+                "id": (!is_placeholder(ws.id)).then_some(ws.id),
+                "idx": ws.idx,
+                "name": ws.name,
+                "output": ws.output,
+                "label": label_text,
+                "bind": bind,
+                "windows": window_count,
+                "visible": visible,
+                "empty": is_empty,
+                "focused": ws.is_focused,
+                "active": ws.is_active,
+                "urgent": ws.is_urgent || urgent_count > 0,
+                "placeholder": is_placeholder(ws.id),
+            }));
 
-                // Store workspace ID for click handler (only if not already set)
-                unsafe {
-                    if button.data::<u64>("ws_id").is_none() {
-                        if !self.config.disable_click {
-                            let ws_id = ws.id;
-                            button.connect_clicked(move |_| {
-                                focus_workspace(ws_id);
-                            });
-                        }
-                        button.set_data("ws_id", ws.id);
-                    }
-                }
-            }
+            // Click and Enter read their target on every use, so they follow workspace changes
+            unsafe { button.set_data("ws_target", (workspace_reference(ws), ws.output.clone())); }
         }
 
         self.container.show_all();
-    }
 
-    fn update_css_class(style_context: &gtk::StyleContext, class: &str, should_have: bool) {
-        if should_have {
-            if !style_context.has_class(class) {
-                style_context.add_class(class);
+        // Workspaces hidden by the visibility policy, so scripts see why they're missing
+        for ws in info.workspaces.iter().filter(|ws| self.config.all_outputs || ws.output == output_name) {
+            if our_workspaces.iter().any(|shown| shown.id == ws.id) {
+                continue;
             }
-        } else {
-            if style_context.has_class(class) {
-                style_context.remove_class(class);
+            view.push(serde_json::json!({
+                "id": ws.id,
+                "idx": ws.idx,
+                "name": ws.name,
+                "output": ws.output,
+                "label": null,
+                "bind": self.binds.borrow().get(ws.name.as_deref(), ws.idx),
+                "windows": info.window_count(ws.id),
+                "visible": false,
+                "empty": self.config.workspace_is_empty(ws, info),
+                "focused": ws.is_focused,
+                "active": ws.is_active,
+                "urgent": ws.is_urgent || info.urgent_count(ws.id) > 0,
+                "placeholder": false,
+            }));
+        }
+        self.bar_output.replace(output_name.clone());
+        if *self.view.borrow() != view {
+            *self.view.borrow_mut() = view;
+            if let Some(service) = &*self.dbus.borrow() {
+                service.emit_changed();
             }
         }
+
+        if let Some(label) = &self.title_label {
+            self.update_window_title(label, info.focused_window.as_ref());
+        }
+
+        // First urgent workspace that isn't already focused
+        let urgent_ws = info.workspaces.iter()
+            .filter(|ws| !ws.is_focused && (ws.is_urgent || info.urgent_count(ws.id) > 0))
+            .min_by_key(|ws| (ws.output.clone(), ws.idx))
+            .map(|ws| ws.id);
+
+        if let Some(indicator) = &self.urgent_indicator {
+            unsafe { indicator.set_data("urgent_ws_id", urgent_ws); }
+            indicator.set_visible(urgent_ws.is_some());
+        }
+
+        if let Some(delay) = self.config.urgent_auto_focus_delay {
+            self.schedule_urgent_focus(urgent_ws, delay);
+        }
     }
-}
 
-impl Module for NiriWorkspaces {
-    type Config = Config;
+    /// Run one of the module's actions, from Waybar's `actions` or D-Bus
+    fn run_action(&self, action: &str) -> Result<(), String> {
+        match action {
+            "toggle-overview" => toggle_overview(),
+            "print-metrics" => eprintln!("niri_workspaces metrics: {}", self.metrics.borrow()),
+            _ => return Err(format!("Unknown action '{}' (expected: toggle-overview, print-metrics)", action)),
+        }
+        Ok(())
+    }
 
-    fn init(info: &InitInfo, config: Config) -> Self {
-        // Load CSS for drag-and-drop styles
-        let css_provider = gtk::CssProvider::new();
-        let css = b"
-            .workspace-button.dragging {
-                opacity: 0.6;
-                background-color: rgba(102, 204, 255, 0.3);
+    fn handle_command(&self, command: dbus::Command) -> Result<dbus::Reply, String> {
+        match command {
+            dbus::Command::GetWorkspaces => {
+                let json = serde_json::to_string(&*self.view.borrow()).map_err(|e| e.to_string())?;
+                return Ok(dbus::Reply::Workspaces(json));
             }
-            .workspace-button.drag-over {
-                background-color: rgba(102, 255, 153, 0.2);
-                border: 1px dashed rgba(102, 255, 153, 0.6);
+            dbus::Command::Refresh => self.populate_workspaces(),
+            dbus::Command::ToggleCurrentOnly => {
+                self.current_only.set(!self.current_only.get());
+                self.populate_workspaces();
+                return Ok(dbus::Reply::CurrentOnly(self.current_only.get()));
             }
-        ";
-        if let Err(e) = css_provider.load_from_data(css) {
-            eprintln!("Failed to load CSS: {}", e);
+            dbus::Command::Rename { workspace, name } => {
+                // niri resolves bare indices on the focused output, so pin them to ours by id
+                let bar_output = self.bar_output.borrow().clone();
+                let reference = parse_reference(&workspace, |idx| workspace_id_at_index(bar_output.as_deref(), idx))?;
+                rename_workspace(reference, name)?;
+            }
+            dbus::Command::Action(action) => self.run_action(&action)?,
         }
-        gtk::StyleContext::add_provider_for_screen(
-            &gtk::gdk::Screen::default().expect("Failed to get default screen"),
-            &css_provider,
-            gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
-        );
+        Ok(dbus::Reply::Done)
+    }
 
-        let container = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+    /// Make the container hold one button per workspace, in order, keeping the buttons of
+    /// workspaces that are still shown so their handlers, hover, focus and drags survive
+    fn reconcile_buttons(&self, workspaces: &[niri_ipc::Workspace]) -> Vec<gtk::Button> {
+        let old_slots = self.container.children();
+        let mut existing: HashMap<u64, gtk::Button> = old_slots
+            .iter()
+            .filter_map(slot_button)
+            .filter_map(|button| {
+                let ws_id = unsafe { button.data::<u64>("ws_id").map(|ptr| *ptr.as_ptr()) }?;
+                Some((ws_id, button))
+            })
+            .collect();
 
-        let root = info.get_root_widget();
-        root.add(&container);
+        let buttons: Vec<gtk::Button> = workspaces
+            .iter()
+            .map(|ws| existing.remove(&ws.id).unwrap_or_else(|| self.create_button(ws)))
+            .collect();
+        let slots: Vec<gtk::Widget> = buttons
+            .iter()
+            .map(|button| match button.parent() {
+                Some(_) => button_slot(button),
+                None => self.create_slot(button),
+            })
+            .collect();
 
-        let module = Self {
-            container,
-            config: config.clone(),
+        // Buttons of workspaces that are gone are removed, or slide out after the slot they followed
+        let mut anchor: Option<gtk::Widget> = None;
+        let mut leaving: Vec<(Option<gtk::Widget>, gtk::Widget)> = Vec::new();
+        for slot in old_slots {
+            if slots.contains(&slot) {
+                anchor = Some(slot);
+            } else if let Some(revealer) = slot.downcast_ref::<gtk::Revealer>().filter(|r| will_animate(r)) {
+                revealer.set_reveal_child(false);
+                leaving.push((anchor.clone(), slot));
+            } else {
+                destroy_slot(&slot);
+            }
+        }
+        let leaving_after = |anchor: Option<&gtk::Widget>| -> Vec<gtk::Widget> {
+            leaving
+                .iter()
+                .filter(|(a, _)| a.as_ref() == anchor)
+                .map(|(_, slot)| slot.clone())
+                .collect()
         };
+        let mut order = leaving_after(None);
+        for slot in &slots {
+            order.push(slot.clone());
+            order.extend(leaving_after(Some(slot)));
+        }
 
-        // Populate initial workspace buttons
-        module.populate_workspaces();
-
-        // Set up event stream listener using glib channel
-        #[allow(deprecated)]
-        let (tx, rx) = gtk::glib::MainContext::channel(gtk::glib::Priority::DEFAULT);
-
-        thread::spawn(move || {
-            loop {
-                // Connect to event stream
-                let mut socket = match Socket::connect() {
-                    Ok(s) => s,
-                    Err(e) => {
-                        eprintln!("Failed to connect to niri socket: {}", e);
-                        thread::sleep(std::time::Duration::from_secs(5));
-                        continue;
-                    }
-                };
-
-                // Request event stream
-                let reply = match socket.send(Request::EventStream) {
-                    Ok(r) => r,
-                    Err(e) => {
-                        eprintln!("Failed to request event stream: {}", e);
-                        thread::sleep(std::time::Duration::from_secs(5));
-                        continue;
-                    }
-                };
-
-                if let Err(e) = reply {
-                    eprintln!("Event stream request failed: {}", e);
-                    thread::sleep(std::time::Duration::from_secs(5));
-                    continue;
-                }
+        // Add new slots and move existing ones only where the order changed
+        for (position, slot) in order.iter().enumerate() {
+            let is_leaving = !slots.contains(slot);
+            if is_leaving && slot.parent().is_none() {
+                // Already finished leaving and destroyed itself; adding it back would strand it
+                continue;
+            }
+            if slot.parent().is_none() {
+                self.container.add(slot);
+                slot.show_all();
+            }
+            if self.container.child_position(slot) != position as i32 {
+                self.container.reorder_child(slot, position as i32);
+            }
+        }
+        for revealer in slots.iter().filter_map(|slot| slot.downcast_ref::<gtk::Revealer>()) {
+            revealer.set_reveal_child(true);
+        }
 
-                // Start reading events
-                let mut read_event = socket.read_events();
-
-                // Listen for events
-                loop {
-                    match read_event() {
-                        Ok(event) => {
-                            // Only signal update on workspace or window changes
-                            match event {
-                                Event::WorkspacesChanged { .. }
-                                | Event::WorkspaceActivated { .. }
-                                | Event::WindowOpenedOrChanged { .. }
-                                | Event::WindowClosed { .. } => {
-                                    let _ = tx.send(());
-                                }
-                                _ => {}
-                            }
-                        }
-                        Err(e) => {
-                            eprintln!("Error reading event: {}", e);
-                            break;
-                        }
-                    }
-                }
+        buttons
+    }
 
-                // Reconnect after a delay if connection is lost
-                thread::sleep(std::time::Duration::from_secs(1));
+    /// The container child for a new button: the button itself, or a revealer around it
+    /// when `workspace_transition` is set
+    fn create_slot(&self, button: &gtk::Button) -> gtk::Widget {
+        let Some(transition) = self.config.workspace_transition.revealer_type() else {
+            return button.clone().upcast();
+        };
+        let revealer = gtk::Revealer::new();
+        revealer.set_transition_type(transition);
+        revealer.set_transition_duration(self.config.workspace_transition_duration);
+        revealer.add(button);
+        // Leaving slots are destroyed once they've finished sliding out
+        revealer.connect_child_revealed_notify(|revealer| {
+            if !revealer.reveals_child() && !revealer.is_child_revealed() {
+                destroy_slot(revealer.upcast_ref());
             }
         });
+        revealer.upcast()
+    }
 
-        // Listen for update signals from the event thread
-        let container_clone = module.container.clone();
-        let config_clone = config.clone();
-        rx.attach(None, move |_| {
-            // Get workspace and window information
-            let (workspaces, window_counts) = match get_workspace_info(&config_clone.ignore_rules) {
-                Ok(data) => data,
-                Err(_) => return gtk::glib::ControlFlow::Continue,
-            };
+    /// A button for `ws` with its handlers; its contents are filled in by `render`
+    fn create_button(&self, ws: &niri_ipc::Workspace) -> gtk::Button {
+        let button = gtk::Button::new();
+        // Add CSS class for styling
+        button.style_context().add_class("workspace-button");
+        // Enable markup for colored icons and center align
+        if let Some(label) = button.child().and_then(|w| w.downcast::<gtk::Label>().ok()) {
+            label.set_use_markup(true);
+            label.set_xalign(0.5);
+            label.set_yalign(0.5);
+            label.set_halign(gtk::Align::Center);
+            label.set_valign(gtk::Align::Center);
+        }
 
-            // Get output name from the first workspace (if any)
-            let output_name = if config_clone.all_outputs {
-                None // Don't filter by output
-            } else {
-                workspaces
-                    .first()
-                    .and_then(|ws| ws.output.clone())
-            };
+        // Window indicators and drawn pie mode: the button holds the icon widget next to the label
+        if let Some(style) = self.config.window_indicators {
+            button.add(&create_button_content(&create_window_indicators(style)));
+        } else if self.config.draws_pie() {
+            button.add(&create_button_content(&create_pie_area(&self.config)));
+        }
 
-            // Find the highest workspace index with windows on this output (or all outputs)
-            let max_workspace_idx = workspaces
-                .iter()
-                .filter(|ws| {
-                    if config_clone.all_outputs {
-                        window_counts.get(&ws.id).copied().unwrap_or(0) > 0
-                    } else {
-                        ws.output == output_name && window_counts.get(&ws.id).copied().unwrap_or(0) > 0
-                    }
-                })
-                .map(|ws| ws.idx)
-                .max()
-                .unwrap_or(0);
-
-            // Filter and sort workspaces
-            let mut our_workspaces: Vec<_> = workspaces
-                .into_iter()
-                .filter(|ws| {
-                    // Filter by output unless all_outputs is enabled
-                    if !config_clone.all_outputs && ws.output != output_name {
-                        return false;
-                    }
+        // Buttons are focusable so the bar can be driven from the keyboard
+        button.set_can_focus(true);
+        if let Some(accessible) = button.accessible() {
+            accessible.set_role(gtk::atk::Role::PushButton);
+        }
 
-                    let has_windows = window_counts.get(&ws.id).copied().unwrap_or(0) > 0;
+        // Set up drag-and-drop for workspace reordering (placeholders don't exist in niri yet)
+        if !is_placeholder(ws.id) {
+            let peek = self.config.hover_peek.then(|| HoverPeek {
+                delay: self.config.hover_peek_delay as u64,
+                state: self.peek_state.clone(),
+            });
+            setup_workspace_drag_drop(&button, ws.id, self.config.drag_hover_focus, self.config.drag_hover_focus_delay as u64, peek);
+        }
 
-                    // Show workspaces with windows, or the next empty workspace based on config
-                    if has_windows {
-                        true
-                    } else if config_clone.show_empty_workspace {
-                        ws.idx == max_workspace_idx + 1  // Always show next empty workspace
-                    } else {
-                        ws.idx == max_workspace_idx + 1 && ws.is_active  // Only show when active
-                    }
-                })
-                .collect();
-
-            // Sort by workspace index to maintain consistent order
-            our_workspaces.sort_by_key(|ws| ws.idx);
-
-            // Get existing buttons
-            let existing_buttons = container_clone.children();
-
-            // Check if we need to rebuild: count changed OR workspace IDs changed
-            let need_rebuild = existing_buttons.len() != our_workspaces.len() || {
-                existing_buttons.iter()
-                    .zip(our_workspaces.iter())
-                    .any(|(button, ws)| {
-                        button.downcast_ref::<gtk::Button>()
-                            .and_then(|b| unsafe { b.data::<u64>("ws_id").map(|ptr| *ptr.as_ptr()) })
-                            .map_or(true, |stored_id| stored_id != ws.id)
-                    })
-            };
+        // Set up arrow-key navigation, Enter to focus and Alt+arrow reordering
+        setup_workspace_keyboard(&button, ws.id);
 
-            if need_rebuild {
-                // Clear existing buttons
-                for child in existing_buttons {
-                    container_clone.remove(&child);
+        if !self.config.disable_click {
+            button.connect_clicked(|button| {
+                if let Some((reference, output)) = workspace_target(button) {
+                    focus_or_create_workspace(reference, output);
                 }
+            });
+        }
+
+        // The key the button is reconciled by; a workspace keeps its id for its lifetime
+        unsafe { button.set_data("ws_id", ws.id); }
+        button
+    }
 
-                // Create new buttons for each workspace with initial state
-                for ws in &our_workspaces {
-                    let button = gtk::Button::new();
-                    let ws_id = ws.id;
+    /// Focus the urgent workspace after `delay` ms, unless it stops being urgent first
+    fn schedule_urgent_focus(&self, urgent_ws: Option<u64>, delay: u32) {
+        let mut pending = self.pending_urgent_focus.borrow_mut();
+        if pending.as_ref().map(|(ws_id, _)| *ws_id) == urgent_ws {
+            return;
+        }
 
-                    // Add CSS class for styling
-                    button.style_context().add_class("workspace-button");
+        if let Some((_, source_id)) = pending.take() {
+            source_id.remove();
+        }
 
-                    // Set initial label
-                    let window_count = window_counts.get(&ws.id).copied().unwrap_or(0);
-                    let value = if let Some(name) = &ws.name {
-                        name.clone()
-                    } else {
-                        ws.idx.to_string()
-                    };
+        if let Some(ws_id) = urgent_ws {
+            let pending_ref = self.pending_urgent_focus.clone();
+            let source_id = gtk::glib::timeout_add_local_once(
+                Duration::from_millis(delay as u64),
+                move || {
+                    pending_ref.borrow_mut().take();
+                    focus_workspace(WorkspaceReferenceArg::Id(ws_id));
+                },
+            );
+            *pending = Some((ws_id, source_id));
+        }
+    }
 
-                    let icon = if config_clone.format_icons.is_some() {
-                        get_format_icon(ws, config_clone.format_icons.as_ref(), &value)
-                    } else {
-                        get_pie_icon(window_count, config_clone.icon_size.as_deref())
-                    };
+    /// Toggle `.urgent-blink` on urgent buttons; removed from everything else
+    fn blink_urgent(container: &gtk::Box) {
+        for button in container.children().iter().filter_map(slot_button) {
+            let style_context = button.style_context();
+            let blink = style_context.has_class("urgent") && !style_context.has_class("urgent-blink");
+            Self::update_css_class(&style_context, "urgent-blink", blink);
+        }
+    }
 
-                    // Escape user-controlled data to prevent markup injection
-                    let escaped_value = gtk::glib::markup_escape_text(&value);
-                    let escaped_name = gtk::glib::markup_escape_text(ws.name.as_deref().unwrap_or(""));
-                    let escaped_index = gtk::glib::markup_escape_text(&ws.idx.to_string());
-                    let escaped_output = gtk::glib::markup_escape_text(ws.output.as_deref().unwrap_or(""));
-
-                    let label_text = if let Some(format) = &config_clone.format {
-                        format
-                            .replace("{icon}", &icon)  // Icon is safe (hardcoded markup or user-provided)
-                            .replace("{value}", &escaped_value)
-                            .replace("{name}", &escaped_name)
-                            .replace("{index}", &escaped_index)
-                            .replace("{output}", &escaped_output)
-                    } else {
-                        icon
-                    };
+    fn update_window_title(&self, label: &gtk::Label, window: Option<&niri_ipc::Window>) {
+        let (Some(window), Some(title_config)) = (window, &self.config.window_title) else {
+            label.set_visible(false);
+            return;
+        };
 
-                    button.set_label(&label_text);
+        let text = title_config.format.render(|field| match field {
+            Field::FocusedTitle => Some(self.config.rewrite_title(window)),
+            Field::FocusedAppId => window.app_id.clone(),
+            _ => None,
+        });
+        label.set_markup(&text);
+        label.set_visible(!text.is_empty());
+    }
 
-                    // Enable markup for colored icons and center align
-                    if let Some(label) = button.child().and_then(|w| w.downcast::<gtk::Label>().ok()) {
-                        label.set_use_markup(true);
-                        label.set_xalign(0.5);
-                        label.set_yalign(0.5);
-                        label.set_halign(gtk::Align::Center);
-                        label.set_valign(gtk::Align::Center);
-                    }
+    fn is_workspace_visible(&self, ws: &niri_ipc::Workspace, info: &WorkspaceInfo, max_idx: u8) -> bool {
+        // Workspaces with windows are always shown
+        if !self.config.workspace_is_empty(ws, info) {
+            return true;
+        }
 
-                    // Store workspace ID and index for drag-drop
-                    unsafe {
-                        button.set_data("ws_id", ws_id);
-                        button.set_data("ws_idx", ws.idx);
-                    }
+        if self.config.always_show_named && ws.name.is_some() {
+            return true;
+        }
 
-                    // Set button name for CSS targeting
-                    button.set_widget_name(&format!("niri-workspace-{}", value));
+        if ws.idx <= self.config.min_workspaces {
+            return true;
+        }
 
-                    // Set initial CSS classes
-                    let style_context = button.style_context();
-                    if ws.is_focused {
-                        style_context.add_class("focused");
-                    }
-                    if ws.is_active {
-                        style_context.add_class("active");
-                    }
-                    if ws.is_urgent {
-                        style_context.add_class("urgent");
-                    }
-                    if ws.active_window_id.is_none() {
-                        style_context.add_class("empty");
-                    }
-                    if let Some(ref bar_output) = output_name {
-                        if ws.output.as_ref() == Some(bar_output) {
-                            style_context.add_class("current_output");
-                        }
-                    }
+        match self.config.workspace_visibility {
+            WorkspaceVisibility::All => true,
+            WorkspaceVisibility::Occupied => false,
+            WorkspaceVisibility::OccupiedFocused => {
+                if self.config.all_outputs {
+                    ws.is_focused
+                } else {
+                    ws.is_active
+                }
+            }
+            WorkspaceVisibility::OccupiedTrailing => ws.idx == max_idx + 1,  // Next empty workspace after occupied ones
+        }
+    }
 
-                    // Set up drag-and-drop for workspace reordering
-                    setup_workspace_drag_drop(&button, ws.id, config_clone.drag_hover_focus, config_clone.drag_hover_focus_delay as u64);
+    fn reload_binds(&self) {
+        *self.binds.borrow_mut() = Binds::load();
+        // Includes may have been added or removed
+        self.watch_niri_config();
+        self.populate_workspaces();
+    }
 
-                    // Set up click handler if not disabled
-                    if !config_clone.disable_click {
-                        button.connect_clicked(move |_| {
-                            focus_workspace(ws_id);
-                        });
-                    }
+    /// Reload binds when niri's config file or a file it includes changes on disk
+    fn watch_niri_config(&self) {
+        let files = self.binds.borrow().files().to_vec();
+        let monitors = files.iter().filter_map(|path| self.watch_config_file(path)).collect();
+        for monitor in self.binds_monitors.replace(monitors) {
+            monitor.cancel();
+        }
+    }
 
-                    container_clone.add(&button);
-                }
+    fn watch_config_file(&self, path: &std::path::Path) -> Option<gtk::gio::FileMonitor> {
+        let monitor = match gtk::gio::File::for_path(path)
+            .monitor_file(gtk::gio::FileMonitorFlags::WATCH_MOVES, None::<&gtk::gio::Cancellable>)
+        {
+            Ok(monitor) => monitor,
+            Err(e) => {
+                eprintln!("Failed to watch niri config {}: {}", path.display(), e);
+                return None;
             }
+        };
+        let module = self.clone();
+        monitor.connect_changed(move |_, _, _, event| {
+            use gtk::gio::FileMonitorEvent;
+            if matches!(
+                event,
+                FileMonitorEvent::ChangesDoneHint
+                    | FileMonitorEvent::Created
+                    | FileMonitorEvent::Deleted
+                    | FileMonitorEvent::Renamed
+                    | FileMonitorEvent::MovedIn
+            ) {
+                module.reload_binds();
+            }
+        });
+        Some(monitor)
+    }
 
-            // Update all buttons with current state
-            let buttons = container_clone.children();
-            for (button, ws) in buttons.iter().zip(our_workspaces.iter()) {
-                if let Some(button) = button.downcast_ref::<gtk::Button>() {
-                    let window_count = window_counts.get(&ws.id).copied().unwrap_or(0);
+    fn set_overview_open(&self, is_open: bool) {
+        Self::update_css_class(&self.container.style_context(), "overview", is_open);
+    }
 
-                    // Determine the display value
-                    let value = if let Some(name) = &ws.name {
-                        name.clone()
-                    } else {
-                        ws.idx.to_string()
-                    };
+    fn update_css_class(style_context: &gtk::StyleContext, class: &str, should_have: bool) {
+        if should_have {
+            if !style_context.has_class(class) {
+                style_context.add_class(class);
+            }
+        } else {
+            if style_context.has_class(class) {
+                style_context.remove_class(class);
+            }
+        }
+    }
+}
 
-                    // Get the icon (either from format-icons or pie chart)
-                    let icon = if config_clone.format_icons.is_some() {
-                        get_format_icon(ws, config_clone.format_icons.as_ref(), &value)
-                    } else {
-                        get_pie_icon(window_count, config_clone.icon_size.as_deref())
-                    };
+impl Module for NiriWorkspaces {
+    type Config = validate::RawConfig;
 
-                    // Build the label using format string or default to icon
-                    // Escape user-controlled data to prevent markup injection
-                    let escaped_value = gtk::glib::markup_escape_text(&value);
-                    let escaped_name = gtk::glib::markup_escape_text(ws.name.as_deref().unwrap_or(""));
-                    let escaped_index = gtk::glib::markup_escape_text(&ws.idx.to_string());
-                    let escaped_output = gtk::glib::markup_escape_text(ws.output.as_deref().unwrap_or(""));
-
-                    let label_text = if let Some(format) = &config_clone.format {
-                        format
-                            .replace("{icon}", &icon)  // Icon is safe (hardcoded markup or user-provided)
-                            .replace("{value}", &escaped_value)
-                            .replace("{name}", &escaped_name)
-                            .replace("{index}", &escaped_index)
-                            .replace("{output}", &escaped_output)
-                    } else {
-                        icon
-                    };
+    fn init(info: &InitInfo, raw: validate::RawConfig) -> Self {
+        let config = validate::parse(raw);
 
-                    // Update button label - always use markup for pie chart icons
-                    button.set_label(&label_text);
-                    if let Some(label) = button.child().and_then(|w| w.downcast::<gtk::Label>().ok()) {
-                        label.set_use_markup(true);
-                    }
+        // Load CSS for drag-and-drop styles
+        let css_provider = gtk::CssProvider::new();
+        let mut css = String::from("
+            .workspace-button.dragging {
+                opacity: 0.6;
+                background-color: rgba(102, 204, 255, 0.3);
+            }
+            .workspace-button.drag-over {
+                background-color: rgba(102, 255, 153, 0.2);
+                border: 1px dashed rgba(102, 255, 153, 0.6);
+            }
+            .window-indicators .window-indicator {
+                background-color: currentColor;
+                opacity: 0.5;
+            }
+            .window-indicators.dots .window-indicator {
+                min-width: 4px;
+                min-height: 4px;
+                border-radius: 2px;
+            }
+            .window-indicators.bars .window-indicator {
+                min-width: 3px;
+                min-height: 10px;
+                border-radius: 1px;
+            }
+            .window-indicators .window-indicator.focused {
+                opacity: 1;
+            }
+            .window-indicators .window-indicator.urgent {
+                background-color: #bf616a;
+                opacity: 1;
+            }
+        ");
+        // Default pie palette as CSS; user styles have higher priority and override it
+        if config.pie_css_classes {
+            css.push_str(&generate_pie_css(&config));
+        }
+        if let Err(e) = css_provider.load_from_data(css.as_bytes()) {
+            eprintln!("Failed to load CSS: {}", e);
+        }
+        gtk::StyleContext::add_provider_for_screen(
+            &gtk::gdk::Screen::default().expect("Failed to get default screen"),
+            &css_provider,
+            gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
+        );
 
-                    // Set button name for CSS targeting
-                    button.set_widget_name(&format!("niri-workspace-{}", value));
-
-                    let style_context = button.style_context();
-
-                    // Update CSS classes using helper function (defined in impl block)
-                    // Since we're in a closure, we need to manually update classes
-                    let update_class = |class: &str, should_have: bool| {
-                        if should_have {
-                            if !style_context.has_class(class) {
-                                style_context.add_class(class);
-                            }
-                        } else {
-                            if style_context.has_class(class) {
-                                style_context.remove_class(class);
-                            }
-                        }
-                    };
+        let container = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+        if let Some(accessible) = container.accessible() {
+            accessible.set_name("Workspaces");
+        }
 
-                    update_class("focused", ws.is_focused);
-                    update_class("active", ws.is_active);
-                    update_class("urgent", ws.is_urgent);
-                    update_class("empty", ws.active_window_id.is_none());
+        // Optional focused window title next to the buttons
+        let title_label = config.window_title.as_ref().map(|title_config| {
+            let label = gtk::Label::new(None);
+            label.set_widget_name("niri-window-title");
+            label.style_context().add_class("window-title");
+            if let Some(max_length) = title_config.max_length {
+                label.set_max_width_chars(max_length);
+                label.set_ellipsize(gtk::pango::EllipsizeMode::End);
+            }
+            // Visibility is managed per update
+            label.set_no_show_all(true);
+            label
+        });
 
-                    // Add current_output class if workspace is on the same output as the bar
-                    if let Some(ref bar_output) = output_name {
-                        update_class("current_output", ws.output.as_ref() == Some(bar_output));
-                    }
+        // Optional button that toggles niri's overview
+        let overview_button = config.overview_button.as_ref().map(|text| {
+            let button = gtk::Button::with_label(text);
+            button.set_widget_name("niri-overview-button");
+            button.style_context().add_class("overview-button");
+            if let Some(label) = button.child().and_then(|w| w.downcast::<gtk::Label>().ok()) {
+                label.set_use_markup(true);
+            }
+            button.connect_clicked(|_| toggle_overview());
+            button
+        });
 
-                    // Handle current_only visibility
-                    if config_clone.current_only {
-                        if config_clone.all_outputs {
-                            button.set_visible(ws.is_focused);
-                        } else {
-                            button.set_visible(ws.is_active);
-                        }
-                    } else {
-                        button.set_visible(true);
-                    }
+        // Optional button that jumps to the urgent workspace
+        let urgent_indicator = config.urgent_indicator.as_ref().map(|text| {
+            let button = gtk::Button::with_label(text);
+            button.set_widget_name("niri-urgent-indicator");
+            button.style_context().add_class("urgent-indicator");
+            if let Some(label) = button.child().and_then(|w| w.downcast::<gtk::Label>().ok()) {
+                label.set_use_markup(true);
+            }
+            button.connect_clicked(|button| {
+                let ws_id = unsafe { button.data::<Option<u64>>("urgent_ws_id").and_then(|ptr| *ptr.as_ptr()) };
+                if let Some(ws_id) = ws_id {
+                    focus_workspace(WorkspaceReferenceArg::Id(ws_id));
                 }
+            });
+            // Only shown while a workspace is urgent
+            button.set_no_show_all(true);
+            button
+        });
+
+        let root = info.get_root_widget();
+        if title_label.is_some() || urgent_indicator.is_some() || overview_button.is_some() {
+            let outer = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+            if let Some(button) = &overview_button {
+                outer.add(button);
+            }
+            outer.add(&container);
+            if let Some(button) = &urgent_indicator {
+                outer.add(button);
+            }
+            if let Some(label) = &title_label {
+                outer.add(label);
             }
+            root.add(&outer);
+        } else {
+            root.add(&container);
+        }
+
+        let workspaces = Workspaces {
+            container,
+            config: config.clone(),
+            title_label,
+            urgent_indicator,
+            pending_urgent_focus: Rc::new(RefCell::new(None)),
+            peek_state: Rc::new(RefCell::new(PeekState::default())),
+            binds: Rc::new(RefCell::new(Binds::load())),
+            binds_monitors: Rc::new(RefCell::new(Vec::new())),
+            pending_state: Rc::new(RefCell::new(None)),
+            render_source: Rc::new(RefCell::new(None)),
+            metrics: Rc::new(RefCell::new(RenderMetrics::default())),
+            current_only: Rc::new(Cell::new(config.current_only)),
+            view: Rc::new(RefCell::new(Vec::new())),
+            bar_output: Rc::new(RefCell::new(None)),
+            dbus: Rc::new(RefCell::new(None)),
+        };
+
+        // Blink urgent workspaces by toggling a class on a timer
+        let blink_timeout = config.urgent_blink_interval.map(|interval| {
+            let container = workspaces.container.clone();
+            gtk::glib::timeout_add_local(Duration::from_millis(interval as u64), move || {
+                Workspaces::blink_urgent(&container);
+                gtk::glib::ControlFlow::Continue
+            })
+        });
+
+        // Log render metrics periodically when asked to
+        let metrics_timeout = config.metrics_interval.filter(|interval| *interval > 0).map(|interval| {
+            let metrics = workspaces.metrics.clone();
+            gtk::glib::timeout_add_seconds_local(interval, move || {
+                eprintln!("niri_workspaces metrics: {}", metrics.borrow());
+                gtk::glib::ControlFlow::Continue
+            })
+        });
 
-            container_clone.show_all();
-            gtk::glib::ControlFlow::Continue
+        workspaces.watch_niri_config();
+
+        // Populate initial workspace buttons
+        workspaces.populate_workspaces();
+        workspaces.set_overview_open(get_overview_open());
+
+        // Listen for niri events; the stream is shared with other bars
+        let subscriber = workspaces.clone();
+        let subscription = events::subscribe(move |event| match event {
+            ModuleEvent::State(snapshot) => subscriber.queue_render(snapshot),
+            ModuleEvent::OverviewChanged(is_open) => subscriber.set_overview_open(is_open),
+            ModuleEvent::NiriConfigLoaded => subscriber.reload_binds(),
+            ModuleEvent::Connection(status) => subscriber.set_connection_status(status),
         });
 
+        // Serve state and commands to scripts over D-Bus
+        if config.dbus {
+            let handler = workspaces.clone();
+            match dbus::Service::register(move |command| handler.handle_command(command)) {
+                Ok(service) => *workspaces.dbus.borrow_mut() = Some(service),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+
         root.show_all();
 
-        module
+        Self {
+            _shutdown: Shutdown {
+                _subscription: subscription,
+                css_provider,
+                blink_timeout,
+                metrics_timeout,
+                pending_urgent_focus: workspaces.pending_urgent_focus.clone(),
+                peek_state: workspaces.peek_state.clone(),
+                render_source: workspaces.render_source.clone(),
+                binds_monitors: workspaces.binds_monitors.clone(),
+                dbus: workspaces.dbus.clone(),
+            },
+            workspaces,
+        }
     }
 
     fn update(&mut self) {
-        self.populate_workspaces();
+        self.workspaces.populate_workspaces();
+    }
+
+    fn do_action(&mut self, action: &str) {
+        if let Err(e) = self.workspaces.run_action(action) {
+            eprintln!("{}", e);
+        }
     }
 }
 
 #[derive(Debug, Clone, Deserialize)]
-#[serde(crate = "waybar_cffi::serde")]
+#[serde(crate = "waybar_cffi::serde", rename_all = "kebab-case")]
 struct Config {
     #[serde(default)]
     ignore_rules: Vec<IgnoreRule>,
-    #[serde(default = "default_show_empty_workspace")]
-    show_empty_workspace: bool,
+    #[serde(default)]
+    workspace_visibility: WorkspaceVisibility,
+    #[serde(default)]
+    min_workspaces: u8,
+    #[serde(default)]
+    always_show_named: bool,
+    #[serde(default)]
+    persistent_workspaces: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    window_title: Option<WindowTitle>,
+    #[serde(default)]
+    urgent_blink_interval: Option<u32>,
+    #[serde(default)]
+    urgent_auto_focus_delay: Option<u32>,
+    #[serde(default)]
+    urgent_indicator: Option<String>,
+    #[serde(default)]
+    overview_button: Option<String>,
     #[serde(default)]
     icon_size: Option<String>,
     #[serde(default)]
+    pie_glyphs: PieGlyphs,
+    #[serde(default = "default_pie_colors")]
+    pie_colors: Vec<String>,
+    /// Ascending and paired with `pie_colors` once checked
+    #[serde(default = "default_pie_thresholds")]
+    pie_thresholds: Vec<usize>,
+    #[serde(default)]
+    pie_css_classes: bool,
+    #[serde(default)]
+    pie_mode: PieMode,
+    #[serde(default = "default_pie_segments")]
+    pie_segments: usize,
+    #[serde(default = "default_pie_size")]
+    pie_size: i32,
+    #[serde(default)]
+    pie_app_colors: HashMap<String, String>,
+    #[serde(default)]
+    window_indicators: Option<IndicatorStyle>,
+    #[serde(default = "default_window_indicators_max")]
+    window_indicators_max: usize,
+    #[serde(default)]
     all_outputs: bool,
     #[serde(default)]
-    format: Option<String>,
-    #[serde(default, rename = "format-icons")]
+    empty_detection: EmptyDetection,
+    #[serde(default)]
+    format: Option<Template>,
+    #[serde(flatten)]
+    state_formats: StateFormats,
+    #[serde(default)]
     format_icons: Option<FormatIcons>,
+    #[serde(default = "default_icon_priority")]
+    icon_priority: Vec<IconKey>,
+    #[serde(default)]
+    combine_icons: bool,
     #[serde(default)]
     disable_click: bool,
     #[serde(default)]
@@ -635,21 +1351,223 @@ struct Config {
     drag_hover_focus: bool,
     #[serde(default = "default_drag_hover_delay")]
     drag_hover_focus_delay: u32,
+    #[serde(default)]
+    hover_peek: bool,
+    #[serde(default = "default_hover_peek_delay")]
+    hover_peek_delay: u32,
+    #[serde(default)]
+    workspace_transition: WorkspaceTransition,
+    #[serde(default = "default_workspace_transition_duration")]
+    workspace_transition_duration: u32,
+    #[serde(default)]
+    render_coalescing: RenderCoalescing,
+    #[serde(default)]
+    max_render_rate: Option<u32>,
+    #[serde(default)]
+    metrics_interval: Option<u32>,
+    #[serde(default)]
+    dbus: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_drag_hover_delay() -> u32 {
+    500
+}
+
+fn default_hover_peek_delay() -> u32 {
+    400
+}
+
+fn default_workspace_transition_duration() -> u32 {
+    250
+}
+
+impl Config {
+    /// Window title after the first matching window_title rewrite rule
+    fn rewrite_title(&self, window: &niri_ipc::Window) -> String {
+        let title = window.title.as_deref().unwrap_or("");
+        let rules = self.window_title.iter().flat_map(|wt| &wt.rewrite);
+
+        for rule in rules {
+            if rule.app_id.as_ref().is_some_and(|app_id| window.app_id.as_ref() != Some(app_id)) {
+                continue;
+            }
+            let captures = match &rule.title {
+                Some(pattern) => match glob_captures(pattern, title) {
+                    Some(captures) => captures,
+                    None => continue,
+                },
+                None => Vec::new(),
+            };
+            return expand_captures(&rule.replace, title, &captures);
+        }
+
+        title.to_string()
+    }
+
+    /// Persistent workspace names for this bar in declared order, with the output to create them on.
+    /// `"*"` entries apply to every output.
+    fn persistent_names(&self, bar_output: Option<&str>) -> Vec<(String, Option<String>)> {
+        let mut outputs: Vec<_> = self.persistent_workspaces.keys()
+            .filter(|output| *output != "*")
+            .filter(|output| self.all_outputs || Some(output.as_str()) == bar_output)
+            .collect();
+        outputs.sort();
+
+        let mut names: Vec<(String, Option<String>)> = Vec::new();
+        let global = self.persistent_workspaces.get("*").into_iter()
+            .flatten()
+            .map(|name| (name, if self.all_outputs { None } else { bar_output.map(str::to_string) }));
+        let per_output = outputs.into_iter().flat_map(|output| {
+            self.persistent_workspaces[output].iter().map(move |name| (name, Some(output.clone())))
+        });
+        for (name, output) in global.chain(per_output) {
+            if !names.iter().any(|(n, _)| n == name) {
+                names.push((name.clone(), output));
+            }
+        }
+        names
+    }
+
+    /// Whether a workspace counts as empty, according to `empty_detection`
+    fn workspace_is_empty(&self, ws: &niri_ipc::Workspace, info: &WorkspaceInfo) -> bool {
+        match self.empty_detection {
+            EmptyDetection::Filtered => info.window_count(ws.id) == 0,
+            EmptyDetection::AllWindows => info.total_counts.get(&ws.id).copied().unwrap_or(0) == 0,
+            EmptyDetection::ActiveWindow => ws.active_window_id.is_none(),
+        }
+    }
+
+    /// Whether window counts are drawn with Cairo rather than shown as glyphs
+    fn draws_pie(&self) -> bool {
+        self.pie_mode != PieMode::Glyph && self.format_icons.is_none()
+    }
+}
+
+fn default_pie_segments() -> usize {
+    8
 }
 
-fn default_show_empty_workspace() -> bool {
-    true
-}
+fn default_pie_size() -> i32 {
+    16
+}
+
+fn default_icon_priority() -> Vec<IconKey> {
+    vec![
+        IconKey::Urgent,
+        IconKey::Empty,
+        IconKey::Focused,
+        IconKey::Active,
+        IconKey::Named,
+        IconKey::Indexed,
+        IconKey::Default,
+    ]
+}
+
+fn default_window_title_format() -> Template {
+    Template::parse("{focused_title}").expect("default window title format is valid")
+}
+
+fn default_window_indicators_max() -> usize {
+    6
+}
+
+fn default_pie_colors() -> Vec<String> {
+    [
+        "#bf616a", // Red
+        "#d08770", // Orange
+        "#ebcb8b", // Yellow
+        "#a3be8c", // Green
+        "#81a1c1", // Blue
+        "#b48ead", // Purple
+        "#8b7355", // Brown
+        "#808080", // Grey
+        "#000000", // Black
+    ]
+    .iter()
+    .map(|c| c.to_string())
+    .collect()
+}
+
+fn default_pie_thresholds() -> Vec<usize> {
+    (7..=15).collect()
+}
+
+/// Workspaces and their windows as seen by the module
+struct WorkspaceInfo {
+    workspaces: Vec<niri_ipc::Workspace>,
+    /// The focused window, even if it matches ignore_rules
+    focused_window: Option<niri_ipc::Window>,
+    /// Windows per workspace id after ignore_rules, in layout order
+    windows: HashMap<u64, Vec<niri_ipc::Window>>,
+    /// Number of windows per workspace id, including ignored ones
+    total_counts: HashMap<u64, usize>,
+}
+
+impl WorkspaceInfo {
+    fn new(mut workspaces: Vec<niri_ipc::Workspace>, windows: Vec<niri_ipc::Window>, ignore_rules: &[IgnoreRule]) -> WorkspaceInfo {
+        // niri doesn't guarantee an order, so keep outputs and indices stable between updates
+        workspaces.sort_by(|a, b| (&a.output, a.idx).cmp(&(&b.output, b.idx)));
+
+        let focused_window = windows.iter().find(|w| w.is_focused).cloned();
+
+        // Group windows per workspace, excluding ignored windows
+        let mut workspace_windows: HashMap<u64, Vec<niri_ipc::Window>> = HashMap::new();
+        let mut total_counts: HashMap<u64, usize> = HashMap::new();
+        for window in windows {
+            if let Some(ws_id) = window.workspace_id {
+                *total_counts.entry(ws_id).or_insert(0) += 1;
+            }
+
+            // Check if window should be ignored
+            let should_ignore = ignore_rules.iter().any(|rule| {
+                let app_id_matches = rule.app_id.as_ref().is_none_or(|app_id| {
+                    window.app_id.as_ref() == Some(app_id)
+                });
+                let title_matches = rule.title.as_ref().is_none_or(|title| {
+                    window.title.as_ref() == Some(title)
+                });
+                app_id_matches && title_matches
+            });
 
-fn default_true() -> bool {
-    true
-}
+            if !should_ignore {
+                if let Some(ws_id) = window.workspace_id {
+                    workspace_windows.entry(ws_id).or_default().push(window);
+                }
+            }
+        }
 
-fn default_drag_hover_delay() -> u32 {
-    500
+        // Order windows by column, then tile, with floating windows last
+        for windows in workspace_windows.values_mut() {
+            windows.sort_by_key(|w| (w.layout.pos_in_scrolling_layout.is_none(), w.layout.pos_in_scrolling_layout, w.id));
+        }
+
+        WorkspaceInfo {
+            workspaces,
+            focused_window,
+            windows: workspace_windows,
+            total_counts,
+        }
+    }
+
+    fn urgent_count(&self, ws_id: u64) -> usize {
+        self.windows(ws_id).iter().filter(|w| w.is_urgent).count()
+    }
+
+    fn windows(&self, ws_id: u64) -> &[niri_ipc::Window] {
+        self.windows.get(&ws_id).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Effective window count: windows left after ignore_rules
+    fn window_count(&self, ws_id: u64) -> usize {
+        self.windows(ws_id).len()
+    }
 }
 
-fn get_workspace_info(ignore_rules: &[IgnoreRule]) -> Result<(Vec<niri_ipc::Workspace>, HashMap<u64, usize>), String> {
+fn get_workspace_info(ignore_rules: &[IgnoreRule]) -> Result<WorkspaceInfo, String> {
     // Get workspaces
     let mut socket = Socket::connect().map_err(|e| e.to_string())?;
     let reply = socket.send(Request::Workspaces).map_err(|e| e.to_string())?;
@@ -668,96 +1586,308 @@ fn get_workspace_info(ignore_rules: &[IgnoreRule]) -> Result<(Vec<niri_ipc::Work
         Err(e) => return Err(e),
     };
 
-    // Count windows per workspace, excluding ignored windows
-    let mut window_counts: HashMap<u64, usize> = HashMap::new();
-    for window in windows {
-        // Check if window should be ignored
-        let should_ignore = ignore_rules.iter().any(|rule| {
-            let app_id_matches = rule.app_id.as_ref().map_or(true, |app_id| {
-                window.app_id.as_ref().map_or(false, |w_app_id| w_app_id == app_id)
-            });
-            let title_matches = rule.title.as_ref().map_or(true, |title| {
-                window.title.as_ref().map_or(false, |w_title| w_title == title)
-            });
-            app_id_matches && title_matches
-        });
+    Ok(WorkspaceInfo::new(workspaces, windows, ignore_rules))
+}
 
-        if !should_ignore {
-            if let Some(ws_id) = window.workspace_id {
-                *window_counts.entry(ws_id).or_insert(0) += 1;
+/// Match `text` against a pattern where `*` matches any run of characters, returning what each `*` matched
+fn glob_captures<'a>(pattern: &str, text: &'a str) -> Option<Vec<&'a str>> {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return (pattern == text).then(Vec::new);
+    }
+
+    let middle = text.strip_prefix(parts[0])?;
+    let mut remaining = middle.strip_suffix(parts[parts.len() - 1])?;
+    let mut captures = Vec::new();
+    for part in &parts[1..parts.len() - 1] {
+        let pos = remaining.find(part)?;
+        captures.push(&remaining[..pos]);
+        remaining = &remaining[pos + part.len()..];
+    }
+    captures.push(remaining);
+    Some(captures)
+}
+
+/// Replace `$0`..`$9` in `template` with the original text and glob captures
+fn expand_captures(template: &str, text: &str, captures: &[&str]) -> String {
+    let mut out = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek().and_then(|d| d.to_digit(10))) {
+            ('$', Some(n)) => {
+                chars.next();
+                let value = if n == 0 { Some(text) } else { captures.get(n as usize - 1).copied() };
+                out.push_str(value.unwrap_or(""));
             }
+            _ => out.push(c),
         }
     }
-
-    Ok((workspaces, window_counts))
+    out
 }
 
-fn get_pie_icon(count: usize, size: Option<&str>) -> String {
-    let size_attr = size.map(|s| format!(" size='{}'", s)).unwrap_or_default();
+fn get_pie_icon(count: usize, config: &Config) -> String {
+    let size_attr = config.icon_size.as_ref().map(|s| format!(" size='{}'", s)).unwrap_or_default();
+
+    // Colors come from CSS classes instead of inline markup when pie_css_classes is set
+    let color_attr = if config.pie_css_classes {
+        String::new()
+    } else {
+        get_pie_color(count, &config.pie_thresholds, &config.pie_colors)
+            .map(|c| format!(" foreground='{}'", c))
+            .unwrap_or_default()
+    };
 
     // Add thin space after icon to center it (compensate for glyph asymmetry)
-    match count {
-        0 => format!("<span{}>󰋙\u{2009}</span>", size_attr),       // Empty hexagon
-        1 => format!("<span{}>󰫃\u{2009}</span>", size_attr),       // 1/8 hexagon
-        2 => format!("<span{}>󰫄\u{2009}</span>", size_attr),       // 2/8 hexagon
-        3 => format!("<span{}>󰫅\u{2009}</span>", size_attr),       // 3/8 hexagon
-        4 => format!("<span{}>󰫆\u{2009}</span>", size_attr),       // 4/8 hexagon
-        5 => format!("<span{}>󰫇\u{2009}</span>", size_attr),       // 5/8 hexagon
-        6 => format!("<span{}>󰫈\u{2009}</span>", size_attr),       // 6/8 hexagon (3/4 filled)
-        7 => format!("<span foreground='#bf616a'{}>󰫈\u{2009}</span>", size_attr),   // Red
-        8 => format!("<span foreground='#d08770'{}>󰫈\u{2009}</span>", size_attr),   // Orange
-        9 => format!("<span foreground='#ebcb8b'{}>󰫈\u{2009}</span>", size_attr),   // Yellow
-        10 => format!("<span foreground='#a3be8c'{}>󰫈\u{2009}</span>", size_attr),  // Green
-        11 => format!("<span foreground='#81a1c1'{}>󰫈\u{2009}</span>", size_attr),  // Blue
-        12 => format!("<span foreground='#b48ead'{}>󰫈\u{2009}</span>", size_attr),  // Purple
-        13 => format!("<span foreground='#8b7355'{}>󰫈\u{2009}</span>", size_attr),  // Brown
-        14 => format!("<span foreground='#808080'{}>󰫈\u{2009}</span>", size_attr),  // Grey
-        _ => format!("<span foreground='#000000'{}>󰫈\u{2009}</span>", size_attr),   // Black (15+)
+    let padding = if config.pie_glyphs.needs_padding() { "\u{2009}" } else { "" };
+
+    format!("<span{}{}>{}{}</span>", color_attr, size_attr, config.pie_glyphs.glyph(count), padding)
+}
+
+/// Color of the highest threshold the count has reached, if any; `thresholds` are ascending
+fn get_pie_color<'a>(count: usize, thresholds: &[usize], colors: &'a [String]) -> Option<&'a str> {
+    thresholds
+        .iter()
+        .zip(colors)
+        .rev()
+        .find(|(threshold, _)| count >= **threshold)
+        .map(|(_, color)| color.as_str())
+}
+
+/// Whether the count has reached the first color threshold
+fn is_crowded(count: usize, config: &Config) -> bool {
+    config.pie_thresholds.first().is_some_and(|&min| count >= min)
+}
+
+/// Stylesheet reproducing the inline pie colors through `count-N` classes
+fn generate_pie_css(config: &Config) -> String {
+    let bands: Vec<_> = config.pie_thresholds.iter().copied().zip(&config.pie_colors).collect();
+
+    let mut css = String::new();
+    for (i, (threshold, color)) in bands.iter().enumerate() {
+        // Each color covers every count up to the next threshold
+        let end = bands.get(i + 1).map_or(*threshold, |(next, _)| next.saturating_sub(1));
+        let selectors: Vec<_> = (*threshold..=end.max(*threshold))
+            .map(|count| format!(".workspace-button.count-{}", count))
+            .collect();
+        css.push_str(&format!("\n{} {{ color: {}; }}", selectors.join(", "), color));
+    }
+    css
+}
+
+/// Count used for `count-N` classes, capped at the last color threshold so the top class covers "N or more"
+fn pie_class_count(count: usize, config: &Config) -> usize {
+    match config.pie_thresholds.last() {
+        Some(&max) => count.min(max),
+        None => count,
     }
 }
 
-fn get_format_icon(ws: &niri_ipc::Workspace, format_icons: Option<&FormatIcons>, value: &str) -> String {
-    if let Some(icons) = format_icons {
-        // Priority order: urgent > empty > focused > active > named > indexed > default
-        if ws.is_urgent {
-            if let Some(icon) = &icons.urgent {
-                return icon.clone();
+fn create_pie_area(config: &Config) -> gtk::DrawingArea {
+    let area = gtk::DrawingArea::new();
+    area.set_size_request(config.pie_size, config.pie_size);
+    area.set_valign(gtk::Align::Center);
+
+    let mode = config.pie_mode;
+    let segments = config.pie_segments.max(1);
+    let overflow = (!config.pie_css_classes)
+        .then(|| (config.pie_thresholds.clone(), config.pie_colors.clone()));
+    let app_colors: HashMap<String, gtk::gdk::RGBA> = config
+        .pie_app_colors
+        .iter()
+        .filter_map(|(app, color)| match color.parse() {
+            Ok(rgba) => Some((app.clone(), rgba)),
+            Err(_) => {
+                eprintln!("Invalid color '{}' for app '{}' in pie-app-colors", color, app);
+                None
+            }
+        })
+        .collect();
+
+    area.connect_draw(move |area, cr| {
+        let apps = unsafe { area.data::<PieApps>("pie_apps").map(|ptr| ptr.as_ref().0.clone()) }
+            .unwrap_or_default();
+
+        // Base color follows the theme (and count-N classes); overflow ramp applies when inline colors are on
+        let mut fg = area.style_context().color(area.state_flags());
+        if let Some((thresholds, colors)) = &overflow {
+            if let Some(rgba) = get_pie_color(apps.len(), thresholds, colors).and_then(|c| c.parse().ok()) {
+                fg = rgba;
             }
         }
+        let segment_colors: Vec<_> = apps
+            .iter()
+            .map(|app| app.as_ref().and_then(|a| app_colors.get(a)).copied().unwrap_or(fg))
+            .collect();
 
-        if ws.active_window_id.is_none() {
-            if let Some(icon) = &icons.empty {
-                return icon.clone();
-            }
+        draw_pie(area, cr, mode, segments, fg, &segment_colors);
+        gtk::glib::Propagation::Stop
+    });
+
+    area
+}
+
+fn create_window_indicators(style: IndicatorStyle) -> gtk::Box {
+    let indicators = gtk::Box::new(gtk::Orientation::Horizontal, 2);
+    indicators.set_valign(gtk::Align::Center);
+    let style_context = indicators.style_context();
+    style_context.add_class("window-indicators");
+    style_context.add_class(match style {
+        IndicatorStyle::Dots => "dots",
+        IndicatorStyle::Bars => "bars",
+    });
+    indicators
+}
+
+/// Rebuild the indicators: one per window up to `max`, then a "+N" overflow label
+fn update_window_indicators(indicators: &gtk::Box, windows: &[niri_ipc::Window], max: usize) {
+    for child in indicators.children() {
+        indicators.remove(&child);
+    }
+
+    let shown = if windows.len() > max { max.saturating_sub(1) } else { windows.len() };
+    for window in &windows[..shown] {
+        let indicator = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+        indicator.set_valign(gtk::Align::Center);
+        let style_context = indicator.style_context();
+        style_context.add_class("window-indicator");
+        if window.is_focused {
+            style_context.add_class("focused");
+        }
+        if window.is_urgent {
+            style_context.add_class("urgent");
         }
+        indicators.add(&indicator);
+    }
 
-        if ws.is_focused {
-            if let Some(icon) = &icons.focused {
-                return icon.clone();
-            }
+    if shown < windows.len() {
+        let overflow = gtk::Label::new(Some(&format!("+{}", windows.len() - shown)));
+        overflow.style_context().add_class("window-indicator-overflow");
+        // Urgent windows hidden in the overflow should still be noticeable
+        if windows[shown..].iter().any(|w| w.is_urgent) {
+            overflow.style_context().add_class("urgent");
         }
+        indicators.add(&overflow);
+    }
 
-        if ws.is_active {
-            if let Some(icon) = &icons.active {
-                return icon.clone();
-            }
+    indicators.show_all();
+}
+
+fn create_button_content(icon: &impl IsA<gtk::Widget>) -> gtk::Box {
+    let content = gtk::Box::new(gtk::Orientation::Horizontal, 4);
+    content.set_halign(gtk::Align::Center);
+
+    let label = gtk::Label::new(None);
+    label.set_use_markup(true);
+    label.set_valign(gtk::Align::Center);
+    // Visibility is managed per update; keep show_all() from revealing an empty label
+    label.set_no_show_all(true);
+
+    content.add(icon);
+    content.add(&label);
+    content
+}
+
+/// Icon widget and label of a button built with `create_button_content`
+fn get_button_content(button: &gtk::Button) -> Option<(gtk::Widget, gtk::Label)> {
+    let content = button.child()?.downcast::<gtk::Box>().ok()?;
+    let children = content.children();
+    let icon = children.first()?.clone();
+    let label = children.get(1)?.clone().downcast::<gtk::Label>().ok()?;
+    Some((icon, label))
+}
+
+fn draw_pie(
+    area: &gtk::DrawingArea,
+    cr: &gtk::cairo::Context,
+    mode: PieMode,
+    segments: usize,
+    fg: gtk::gdk::RGBA,
+    segment_colors: &[gtk::gdk::RGBA],
+) {
+    use std::f64::consts::{FRAC_PI_2, TAU};
+
+    let width = area.allocated_width() as f64;
+    let height = area.allocated_height() as f64;
+    let (cx, cy) = (width / 2.0, height / 2.0);
+    let radius = width.min(height) / 2.0 - 1.0;
+    if radius <= 0.0 {
+        return;
+    }
+
+    let ring_width = (radius * 0.35).max(1.5);
+    let set_color = |color: &gtk::gdk::RGBA, alpha: f64| {
+        cr.set_source_rgba(color.red(), color.green(), color.blue(), color.alpha() * alpha);
+    };
+
+    // Faint track showing the unfilled part
+    set_color(&fg, 0.25);
+    match mode {
+        PieMode::Ring => {
+            cr.set_line_width(ring_width);
+            cr.arc(cx, cy, radius - ring_width / 2.0, 0.0, TAU);
+            let _ = cr.stroke();
         }
+        _ => {
+            cr.set_line_width(1.0);
+            cr.arc(cx, cy, radius - 0.5, 0.0, TAU);
+            let _ = cr.stroke();
+        }
+    }
 
-        // Check for named workspace icons
-        if let Some(name) = &ws.name {
-            if let Some(icon) = icons.named.get(name) {
-                return icon.clone();
+    // One segment per window, starting at 12 o'clock; the pie is full at pie_segments windows
+    let filled = segment_colors.len().min(segments);
+    let step = TAU / segments as f64;
+    let gap = if segments > 1 && filled > 1 { (0.08_f64).min(step / 4.0) } else { 0.0 };
+    for (i, color) in segment_colors.iter().take(filled).enumerate() {
+        let start = -FRAC_PI_2 + i as f64 * step + gap / 2.0;
+        let end = -FRAC_PI_2 + (i + 1) as f64 * step - gap / 2.0;
+        set_color(color, 1.0);
+        match mode {
+            PieMode::Ring => {
+                cr.set_line_width(ring_width);
+                cr.arc(cx, cy, radius - ring_width / 2.0, start, end);
+                let _ = cr.stroke();
+            }
+            _ => {
+                cr.move_to(cx, cy);
+                cr.arc(cx, cy, radius, start, end);
+                cr.close_path();
+                let _ = cr.fill();
             }
         }
+    }
+}
+
+fn get_format_icon(ws: &niri_ipc::Workspace, is_empty: bool, config: &Config, value: &str) -> String {
+    if let Some(icons) = &config.format_icons {
+        let priority = &config.icon_priority;
 
-        // Check for index-based icons
+        // Compound keys like "focused-browser" or "urgent-3" win over plain state and name icons
         let idx_str = ws.idx.to_string();
-        if let Some(icon) = icons.named.get(&idx_str) {
-            return icon.clone();
+        for key in priority.iter().filter(|key| key.is_state() && key.applies(ws, is_empty)) {
+            for id in ws.name.iter().chain(std::iter::once(&idx_str)) {
+                if let Some(icon) = icons.named.get(&format!("{}-{}", key.name(), id)) {
+                    return icon.clone();
+                }
+            }
         }
 
-        // Fall back to default
-        if let Some(icon) = &icons.default {
+        if config.combine_icons {
+            // Render the first matching state icon followed by the name/index/default icon
+            let state_icon = priority.iter()
+                .filter(|key| key.is_state())
+                .find_map(|key| lookup_format_icon(icons, ws, is_empty, *key));
+            let workspace_icon = priority.iter()
+                .filter(|key| !key.is_state())
+                .find_map(|key| lookup_format_icon(icons, ws, is_empty, *key));
+            if state_icon.is_some() || workspace_icon.is_some() {
+                return format!(
+                    "{}{}",
+                    state_icon.map_or("", String::as_str),
+                    workspace_icon.map_or("", String::as_str)
+                );
+            }
+        } else if let Some(icon) = priority.iter().find_map(|key| lookup_format_icon(icons, ws, is_empty, *key)) {
             return icon.clone();
         }
     }
@@ -766,19 +1896,232 @@ fn get_format_icon(ws: &niri_ipc::Workspace, format_icons: Option<&FormatIcons>,
     value.to_string()
 }
 
-fn focus_workspace(id: u64) {
+fn lookup_format_icon<'a>(icons: &'a FormatIcons, ws: &niri_ipc::Workspace, is_empty: bool, key: IconKey) -> Option<&'a String> {
+    if !key.applies(ws, is_empty) {
+        return None;
+    }
+
+    match key {
+        IconKey::Urgent => icons.urgent.as_ref(),
+        IconKey::Empty => icons.empty.as_ref(),
+        IconKey::Focused => icons.focused.as_ref(),
+        IconKey::Active => icons.active.as_ref(),
+        IconKey::Named => ws.name.as_ref().and_then(|name| icons.named.get(name)),
+        IconKey::Indexed => icons.named.get(&ws.idx.to_string()),
+        IconKey::Default => icons.default.as_ref(),
+    }
+}
+
+fn get_format<'a>(ws: &niri_ipc::Workspace, is_empty: bool, config: &'a Config) -> Option<&'a Template> {
+    let formats = &config.state_formats;
+
+    // Same priority order as format-icons, falling back to format
+    config.icon_priority.iter()
+        .filter(|key| key.applies(ws, is_empty))
+        .find_map(|key| match key {
+            IconKey::Urgent => formats.urgent.as_ref(),
+            IconKey::Empty => formats.empty.as_ref(),
+            IconKey::Focused => formats.focused.as_ref(),
+            IconKey::Active => formats.active.as_ref(),
+            IconKey::Named => ws.name.as_ref().and_then(|name| formats.named.get(name)),
+            IconKey::Indexed => formats.named.get(&ws.idx.to_string()),
+            IconKey::Default => None,
+        })
+        .or(config.format.as_ref())
+}
+
+fn get_accessible_name(ws: &niri_ipc::Workspace, window_count: usize) -> String {
+    let windows = match window_count {
+        0 => "no windows".to_string(),
+        1 => "1 window".to_string(),
+        n => format!("{} windows", n),
+    };
+
+    match &ws.name {
+        Some(name) if ws.idx == 0 => format!("Workspace {}, {}", name, windows),  // Not created yet
+        Some(name) => format!("Workspace {}: {}, {}", ws.idx, name, windows),
+        None => format!("Workspace {}, {}", ws.idx, windows),
+    }
+}
+
+fn get_accessible_state(ws: &niri_ipc::Workspace, is_empty: bool) -> String {
+    let mut states = Vec::new();
+    if ws.is_focused {
+        states.push("focused".to_string());
+    } else if ws.is_active {
+        states.push("active".to_string());
+    }
+    if ws.is_urgent {
+        states.push("urgent".to_string());
+    }
+    if is_empty {
+        states.push("empty".to_string());
+    }
+    if let Some(output) = &ws.output {
+        states.push(format!("on {}", output));
+    }
+    states.join(", ")
+}
+
+/// Placeholder ids have the top bit set; real niri ids never get that large
+const PLACEHOLDER_ID_BIT: u64 = 1 << 63;
+
+fn is_placeholder(ws_id: u64) -> bool {
+    ws_id & PLACEHOLDER_ID_BIT != 0
+}
+
+/// A stand-in for a workspace index or name that niri hasn't created
+fn placeholder_workspace(output: Option<String>, idx: u8, name: Option<String>) -> niri_ipc::Workspace {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    (&output, idx, &name).hash(&mut hasher);
+
+    niri_ipc::Workspace {
+        id: hasher.finish() | PLACEHOLDER_ID_BIT,
+        idx,
+        name,
+        output,
+        is_urgent: false,
+        is_active: false,
+        is_focused: false,
+        active_window_id: None,
+    }
+}
+
+/// How to address a workspace in niri actions; placeholders go by name or index
+fn workspace_reference(ws: &niri_ipc::Workspace) -> WorkspaceReferenceArg {
+    match &ws.name {
+        _ if !is_placeholder(ws.id) => WorkspaceReferenceArg::Id(ws.id),
+        Some(name) => WorkspaceReferenceArg::Name(name.clone()),
+        None => WorkspaceReferenceArg::Index(ws.idx),
+    }
+}
+
+/// Focus a workspace, first creating named workspaces that don't exist yet
+fn focus_or_create_workspace(reference: WorkspaceReferenceArg, output: Option<String>) {
+    match &reference {
+        WorkspaceReferenceArg::Name(name) => {
+            if let Err(e) = ensure_named_workspace(name, output.as_deref()) {
+                eprintln!("Failed to create workspace '{}': {}", name, e);
+            }
+        }
+        // Indices are relative to the focused output, so focus the placeholder's output first
+        WorkspaceReferenceArg::Index(_) => {
+            if let (Some(output), Ok(mut socket)) = (output, Socket::connect()) {
+                let _ = socket.send(Request::Action(Action::FocusMonitor { output }));
+            }
+        }
+        WorkspaceReferenceArg::Id(_) => {}
+    }
+    focus_workspace(reference);
+}
+
+fn ensure_named_workspace(name: &str, output: Option<&str>) -> Result<(), String> {
+    let mut socket = Socket::connect().map_err(|e| e.to_string())?;
+    let reply = socket.send(Request::Workspaces).map_err(|e| e.to_string())?;
+    let workspaces = match reply {
+        Ok(Response::Workspaces(ws)) => ws,
+        Ok(_) => return Err("Unexpected response type".to_string()),
+        Err(e) => return Err(e),
+    };
+
+    if workspaces.iter().any(|ws| ws.name.as_deref() == Some(name)) {
+        return Ok(());
+    }
+
+    // Name the trailing empty workspace on the target output (or the focused one)
+    let output = output
+        .map(str::to_string)
+        .or_else(|| workspaces.iter().find(|ws| ws.is_focused).and_then(|ws| ws.output.clone()));
+    let target = workspaces
+        .iter()
+        .filter(|ws| ws.output == output && ws.name.is_none() && ws.active_window_id.is_none())
+        .max_by_key(|ws| ws.idx)
+        .ok_or("no empty workspace to name")?;
+
+    let mut socket = Socket::connect().map_err(|e| e.to_string())?;
+    let reply = socket.send(Request::Action(Action::SetWorkspaceName {
+        name: name.to_string(),
+        workspace: Some(WorkspaceReferenceArg::Id(target.id)),
+    })).map_err(|e| e.to_string())?;
+
+    match reply {
+        Ok(Response::Handled) => Ok(()),
+        Ok(_) => Err("Failed to name workspace".to_string()),
+        Err(e) => Err(e),
+    }
+}
+
+fn get_overview_open() -> bool {
+    let Ok(mut socket) = Socket::connect() else {
+        return false;
+    };
+    matches!(socket.send(Request::OverviewState), Ok(Ok(Response::OverviewState(overview))) if overview.is_open)
+}
+
+fn toggle_overview() {
     if let Ok(mut socket) = Socket::connect() {
-        let _ = socket.send(Request::Action(Action::FocusWorkspace {
-            reference: WorkspaceReferenceArg::Id(id),
-        }));
+        let _ = socket.send(Request::Action(Action::ToggleOverview {}));
+    }
+}
+
+/// Id of the workspace at `idx` on `output`, or on the focused output when `None`
+/// A workspace given over D-Bus as `id:<id>`, `idx:<index>` or `name:<name>`, with indices
+/// turned into ids by `index_to_id`
+fn parse_reference(
+    workspace: &str,
+    index_to_id: impl FnOnce(u8) -> Result<u64, String>,
+) -> Result<WorkspaceReferenceArg, String> {
+    let invalid = || format!("invalid workspace '{}' (expected id:<id>, idx:<index> or name:<name>)", workspace);
+    match workspace.split_once(':') {
+        Some(("id", id)) => id.parse().map(WorkspaceReferenceArg::Id).map_err(|_| invalid()),
+        Some(("idx", idx)) => index_to_id(idx.parse().map_err(|_| invalid())?).map(WorkspaceReferenceArg::Id),
+        Some(("name", name)) if !name.is_empty() => Ok(WorkspaceReferenceArg::Name(name.to_string())),
+        _ => Err(invalid()),
+    }
+}
+
+fn workspace_id_at_index(output: Option<&str>, idx: u8) -> Result<u64, String> {
+    let mut socket = Socket::connect().map_err(|e| e.to_string())?;
+    let workspaces = match socket.send(Request::Workspaces).map_err(|e| e.to_string())? {
+        Ok(Response::Workspaces(ws)) => ws,
+        Ok(_) => return Err("Unexpected response type".to_string()),
+        Err(e) => return Err(e),
+    };
+
+    let output = output
+        .map(str::to_string)
+        .or_else(|| workspaces.iter().find(|ws| ws.is_focused).and_then(|ws| ws.output.clone()));
+    workspaces
+        .iter()
+        .find(|ws| ws.output == output && ws.idx == idx)
+        .map(|ws| ws.id)
+        .ok_or_else(|| format!("no workspace at index {} on {}", idx, output.as_deref().unwrap_or("the focused output")))
+}
+
+/// Name a workspace, or remove its name when `name` is empty
+fn rename_workspace(reference: WorkspaceReferenceArg, name: String) -> Result<(), String> {
+    let action = if name.is_empty() {
+        Action::UnsetWorkspaceName { reference: Some(reference) }
+    } else {
+        Action::SetWorkspaceName { name, workspace: Some(reference) }
+    };
+    let mut socket = Socket::connect().map_err(|e| e.to_string())?;
+    match socket.send(Request::Action(action)).map_err(|e| e.to_string())? {
+        Ok(Response::Handled) => Ok(()),
+        Ok(_) => Err("Failed to rename workspace".to_string()),
+        Err(e) => Err(e),
     }
 }
 
-fn setup_workspace_drag_drop(button: &gtk::Button, ws_id: u64, drag_hover_focus: bool, drag_hover_delay: u64) {
-    use std::cell::RefCell;
-    use std::rc::Rc;
-    use std::time::Duration;
+fn focus_workspace(reference: WorkspaceReferenceArg) {
+    if let Ok(mut socket) = Socket::connect() {
+        let _ = socket.send(Request::Action(Action::FocusWorkspace { reference }));
+    }
+}
 
+fn setup_workspace_drag_drop(button: &gtk::Button, ws_id: u64, drag_hover_focus: bool, drag_hover_delay: u64, peek: Option<HoverPeek>) {
     // Internal drag target for workspace reordering
     let internal_targets = vec![gtk::TargetEntry::new(
         "application/x-workspace",
@@ -816,14 +2159,21 @@ fn setup_workspace_drag_drop(button: &gtk::Button, ws_id: u64, drag_hover_focus:
     let timeout_for_motion = hover_timeout.clone();
     let timeout_for_leave = hover_timeout.clone();
     let timeout_for_drop = hover_timeout.clone();
+    let timeout_for_peek_enter = hover_timeout.clone();
+    let timeout_for_peek_leave = hover_timeout.clone();
+
+    // Don't let a pending hover focus fire once the button is gone
+    let timeout_for_destroy = hover_timeout.clone();
+    button.connect_destroy(move |_| {
+        if let Some(timeout_id) = timeout_for_destroy.borrow_mut().take() {
+            timeout_id.remove();
+        }
+    });
 
     button.connect_drag_begin(move |widget, _| {
         // Store the starting index
-        if let Some(parent) = widget.parent() {
-            if let Ok(container) = parent.downcast::<gtk::Box>() {
-                let pos = container.child_position(widget);
-                *start_index_begin.borrow_mut() = pos as usize;
-            }
+        if let Some(pos) = workspace_container(widget).and_then(|container| button_position(&container, widget)) {
+            *start_index_begin.borrow_mut() = pos;
         }
 
         widget.style_context().add_class("dragging");
@@ -840,14 +2190,22 @@ fn setup_workspace_drag_drop(button: &gtk::Button, ws_id: u64, drag_hover_focus:
         button_for_end.style_context().remove_class("dragging");
 
         // Get the final position after visual reordering
-        if let Some(parent) = widget.parent() {
-            if let Ok(container) = parent.downcast::<gtk::Box>() {
-                let final_pos = container.child_position(widget) as usize;
+        if let Some(container) = workspace_container(widget) {
+            let buttons = container_buttons(&container);
+            if let Some(final_pos) = buttons.iter().position(|button| button == widget) {
                 let start_pos = *start_index_end.borrow();
 
                 if final_pos != start_pos {
-                    // Position in GTK maps to workspace index (1-based)
-                    let target_idx = final_pos + 1;
+                    // Take the index of the workspace we displaced, so hidden empty workspaces
+                    // between buttons don't shift the target; fall back to position (1-based)
+                    let displaced = if final_pos > start_pos {
+                        buttons.get(final_pos - 1)
+                    } else {
+                        buttons.get(final_pos + 1)
+                    };
+                    let target_idx = displaced
+                        .and_then(|w| unsafe { w.data::<u8>("ws_idx").map(|ptr| *ptr.as_ptr()) })
+                        .map_or(final_pos + 1, |idx| idx as usize);
                     let _ = move_workspace_to_index(ws_id, target_idx);
                 }
             }
@@ -870,7 +2228,7 @@ fn setup_workspace_drag_drop(button: &gtk::Button, ws_id: u64, drag_hover_focus:
                 let source_id = gtk::glib::timeout_add_local_once(
                     Duration::from_millis(drag_hover_delay),
                     move || {
-                        focus_workspace(ws_id);
+                        focus_workspace(WorkspaceReferenceArg::Id(ws_id));
                         timeout_ref.borrow_mut().take();
                     },
                 );
@@ -880,16 +2238,15 @@ fn setup_workspace_drag_drop(button: &gtk::Button, ws_id: u64, drag_hover_focus:
         }
 
         // Internal drag: reorder workspace buttons visually
-        if let Some(source) = ctx.drag_get_source_widget() {
+        if let Some(source) = ctx.drag_get_source_widget().and_then(|s| s.downcast::<gtk::Button>().ok()) {
             if source != *widget {
-                if let Some(parent) = widget.parent() {
-                    if let Ok(container) = parent.downcast::<gtk::Box>() {
-                        let source_pos = container.child_position(&source);
-                        let target_pos = container.child_position(widget);
-
-                        if source_pos != target_pos {
-                            container.reorder_child(&source, target_pos);
-                        }
+                if let Some(container) = workspace_container(widget) {
+                    let source_slot = button_slot(&source);
+                    let source_pos = container.child_position(&source_slot);
+                    let target_pos = container.child_position(&button_slot(widget));
+
+                    if source_pos != target_pos {
+                        container.reorder_child(&source_slot, target_pos);
                     }
                 }
             }
@@ -915,6 +2272,180 @@ fn setup_workspace_drag_drop(button: &gtk::Button, ws_id: u64, drag_hover_focus:
         // Reject external drops (we don't want to receive the file data)
         ctx.drag_get_source_widget().is_some()
     });
+
+    // Hover peek: focus the workspace after hovering, restore the previous one on leave
+    let Some(peek) = peek else {
+        return;
+    };
+
+    let peek_enter = peek.clone();
+    button.connect_enter_notify_event(move |_, _| {
+        if let Some(restore) = peek_enter.state.borrow_mut().restore_timeout.take() {
+            restore.remove();
+        }
+        if timeout_for_peek_enter.borrow().is_none() {
+            let timeout_ref = timeout_for_peek_enter.clone();
+            let state_ref = peek_enter.state.clone();
+            let source_id = gtk::glib::timeout_add_local_once(
+                Duration::from_millis(peek_enter.delay),
+                move || {
+                    timeout_ref.borrow_mut().take();
+                    let mut state = state_ref.borrow_mut();
+                    if state.focused == Some(ws_id) {
+                        return;
+                    }
+                    // Focus may be on another output's bar, so take it from niri's state, not our buttons
+                    if state.origin.is_none() {
+                        state.origin = state.focused;
+                    }
+                    focus_workspace(WorkspaceReferenceArg::Id(ws_id));
+                },
+            );
+            *timeout_for_peek_enter.borrow_mut() = Some(source_id);
+        }
+        gtk::glib::Propagation::Proceed
+    });
+
+    let peek_leave = peek.clone();
+    button.connect_leave_notify_event(move |_, _| {
+        if let Some(timeout_id) = timeout_for_peek_leave.borrow_mut().take() {
+            timeout_id.remove();
+        }
+        let mut state = peek_leave.state.borrow_mut();
+        if state.origin.is_some() && state.restore_timeout.is_none() {
+            let state_ref = peek_leave.state.clone();
+            let source_id = gtk::glib::timeout_add_local_once(
+                Duration::from_millis(PEEK_RESTORE_DELAY_MS),
+                move || {
+                    let mut state = state_ref.borrow_mut();
+                    state.restore_timeout = None;
+                    if let Some(origin) = state.origin.take() {
+                        focus_workspace(WorkspaceReferenceArg::Id(origin));
+                    }
+                },
+            );
+            state.restore_timeout = Some(source_id);
+        }
+        gtk::glib::Propagation::Proceed
+    });
+
+    // Clicking commits the peeked workspace
+    button.connect_clicked(move |_| {
+        let mut state = peek.state.borrow_mut();
+        state.origin = None;
+        if let Some(restore) = state.restore_timeout.take() {
+            restore.remove();
+        }
+    });
+}
+
+/// Take a slot out of the container for good. Destroying rather than removing it runs the
+/// button's destroy handlers and drops its signal handlers, whose closures hold the button
+/// and would otherwise keep it and its hover timeout alive.
+fn destroy_slot(slot: &gtk::Widget) {
+    // The slot is no longer in use: it's out of the order and nothing keeps it to re-add
+    unsafe { slot.destroy() }
+}
+
+/// Whether hiding `revealer` will animate, and so notify `child-revealed` later rather than
+/// while the caller is still reordering slots
+fn will_animate(revealer: &gtk::Revealer) -> bool {
+    let animations = gtk::Settings::default().is_none_or(|settings| settings.property::<bool>("gtk-enable-animations"));
+    animations && revealer.is_mapped() && revealer.transition_duration() > 0
+}
+
+/// The container child holding a workspace button: the button, or the revealer around it
+fn button_slot(button: &gtk::Button) -> gtk::Widget {
+    button
+        .parent()
+        .filter(|parent| parent.is::<gtk::Revealer>())
+        .unwrap_or_else(|| button.clone().upcast())
+}
+
+/// The workspace button in a container child
+fn slot_button(slot: &gtk::Widget) -> Option<gtk::Button> {
+    match slot.downcast_ref::<gtk::Revealer>() {
+        Some(revealer) => revealer.child()?.downcast().ok(),
+        None => slot.clone().downcast().ok(),
+    }
+}
+
+fn workspace_container(button: &gtk::Button) -> Option<gtk::Box> {
+    button_slot(button).parent()?.downcast().ok()
+}
+
+/// Buttons in the container in order, leaving out those sliding out
+fn container_buttons(container: &gtk::Box) -> Vec<gtk::Button> {
+    container
+        .children()
+        .iter()
+        .filter(|slot| slot.downcast_ref::<gtk::Revealer>().is_none_or(|revealer| revealer.reveals_child()))
+        .filter_map(slot_button)
+        .collect()
+}
+
+fn button_position(container: &gtk::Box, button: &gtk::Button) -> Option<usize> {
+    container_buttons(container).iter().position(|b| b == button)
+}
+
+/// Where clicking or pressing Enter on a workspace button goes, as last set by `render`
+fn workspace_target(button: &gtk::Button) -> Option<(WorkspaceReferenceArg, Option<String>)> {
+    unsafe {
+        button
+            .data::<(WorkspaceReferenceArg, Option<String>)>("ws_target")
+            .map(|ptr| ptr.as_ref().clone())
+    }
+}
+
+fn setup_workspace_keyboard(button: &gtk::Button, ws_id: u64) {
+    use gtk::gdk::keys::constants as keys;
+
+    button.connect_key_press_event(move |widget, event| {
+        let keyval = event.keyval();
+        let alt = event.state().contains(gtk::gdk::ModifierType::MOD1_MASK);
+
+        let is_prev = keyval == keys::Left || keyval == keys::Up;
+        let is_next = keyval == keys::Right || keyval == keys::Down;
+
+        if keyval == keys::Return || keyval == keys::KP_Enter || keyval == keys::space {
+            if let Some((reference, output)) = workspace_target(widget) {
+                focus_or_create_workspace(reference, output);
+            }
+            return gtk::glib::Propagation::Stop;
+        }
+
+        if !is_prev && !is_next {
+            return gtk::glib::Propagation::Proceed;
+        }
+
+        // Find the previous/next visible workspace button
+        let Some(container) = workspace_container(widget) else {
+            return gtk::glib::Propagation::Proceed;
+        };
+        let siblings: Vec<_> = container_buttons(&container)
+            .into_iter()
+            .filter(|button| button.is_visible())
+            .collect();
+        let target = siblings
+            .iter()
+            .position(|button| button == widget)
+            .and_then(|pos| if is_prev { pos.checked_sub(1) } else { Some(pos + 1) })
+            .and_then(|t| siblings.get(t));
+
+        if alt {
+            // Alt+arrow swaps the workspace into its neighbour's index, skipping hidden empty workspaces
+            let target_idx = target.and_then(|t| unsafe { t.data::<u8>("ws_idx").map(|ptr| *ptr.as_ptr()) });
+            if let Some(target_idx) = target_idx.filter(|&idx| idx >= 1 && !is_placeholder(ws_id)) {
+                if let Err(e) = move_workspace_to_index(ws_id, target_idx as usize) {
+                    eprintln!("Failed to move workspace: {}", e);
+                }
+            }
+        } else if let Some(target) = target {
+            // Plain arrows move keyboard focus between visible workspace buttons
+            target.grab_focus();
+        }
+        gtk::glib::Propagation::Stop
+    });
 }
 
 fn move_workspace_to_index(ws_id: u64, target_index: usize) -> Result<(), String> {
@@ -957,3 +2488,125 @@ fn move_workspace_to_index(ws_id: u64, target_index: usize) -> Result<(), String
 }
 
 waybar_module!(NiriWorkspaces);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn index_to_id(idx: u8) -> Result<u64, String> {
+        match idx {
+            2 => Ok(42),
+            _ => Err(format!("no workspace at index {}", idx)),
+        }
+    }
+
+    #[test]
+    fn workspace_references() {
+        assert_eq!(parse_reference("id:7", index_to_id), Ok(WorkspaceReferenceArg::Id(7)));
+        assert_eq!(parse_reference("idx:2", index_to_id), Ok(WorkspaceReferenceArg::Id(42)));
+        assert_eq!(parse_reference("idx:3", index_to_id), Err("no workspace at index 3".to_string()));
+        assert_eq!(
+            parse_reference("name:mail:work", index_to_id),
+            Ok(WorkspaceReferenceArg::Name("mail:work".to_string()))
+        );
+    }
+
+    #[test]
+    fn invalid_workspace_references() {
+        for workspace in ["name:", "id:x", "idx:-1", "idx:256", "index:2", "mail", ""] {
+            assert_eq!(
+                parse_reference(workspace, |_| panic!("{} resolved an index", workspace)),
+                Err(format!("invalid workspace '{}' (expected id:<id>, idx:<index> or name:<name>)", workspace))
+            );
+        }
+    }
+
+    #[test]
+    fn glob_without_star_matches_exactly() {
+        assert_eq!(glob_captures("Firefox", "Firefox"), Some(vec![]));
+        assert_eq!(glob_captures("Firefox", "Firefox Nightly"), None);
+    }
+
+    #[test]
+    fn glob_leading_and_trailing_star() {
+        assert_eq!(glob_captures("* - Firefox", "Docs - Firefox"), Some(vec!["Docs"]));
+        assert_eq!(glob_captures("* - Firefox", "Docs - Chromium"), None);
+        assert_eq!(glob_captures("vim *", "vim notes.txt"), Some(vec!["notes.txt"]));
+        assert_eq!(glob_captures("vim *", "vim "), Some(vec![""]));
+        assert_eq!(glob_captures("*", "anything"), Some(vec!["anything"]));
+    }
+
+    #[test]
+    fn glob_prefix_and_suffix_dont_overlap() {
+        assert_eq!(glob_captures("ab*bc", "abc"), None);
+        assert_eq!(glob_captures("ab*bc", "abbc"), Some(vec![""]));
+    }
+
+    #[test]
+    fn glob_several_stars() {
+        assert_eq!(glob_captures("*-*-*", "a-b-c"), Some(vec!["a", "b", "c"]));
+        assert_eq!(glob_captures("* - * - *", "a - b - c - d"), Some(vec!["a", "b", "c - d"]));
+        assert_eq!(glob_captures("*: * (*)", "mutt: inbox"), None);
+    }
+
+    #[test]
+    fn expand_whole_text_and_captures() {
+        assert_eq!(expand_captures("[$0]", "Docs - Firefox", &["Docs"]), "[Docs - Firefox]");
+        assert_eq!(expand_captures("$2 / $1", "", &["a", "b"]), "b / a");
+        assert_eq!(expand_captures("$3!", "", &["a", "b"]), "!");
+        assert_eq!(expand_captures("$10", "", &["a"]), "a0");
+        assert_eq!(expand_captures("$ $x costs $", "", &["a"]), "$ $x costs $");
+    }
+
+    fn window(app_id: Option<&str>, title: &str) -> niri_ipc::Window {
+        niri_ipc::Window {
+            id: 1,
+            title: Some(title.to_string()),
+            app_id: app_id.map(str::to_string),
+            pid: None,
+            workspace_id: None,
+            is_focused: false,
+            is_floating: false,
+            is_urgent: false,
+            layout: niri_ipc::WindowLayout {
+                pos_in_scrolling_layout: None,
+                tile_size: (0.0, 0.0),
+                window_size: (0, 0),
+                tile_pos_in_workspace_view: None,
+                window_offset_in_tile: (0.0, 0.0),
+            },
+            focus_timestamp: None,
+        }
+    }
+
+    fn rewriting(rules: serde_json::Value) -> Config {
+        serde_json::from_value(json!({"window-title": {"rewrite": rules}})).expect("the test config is valid")
+    }
+
+    #[test]
+    fn rewrite_app_id_only_rule() {
+        let config = rewriting(json!([{"app-id": "kitty", "replace": "term: $0"}]));
+        assert_eq!(config.rewrite_title(&window(Some("kitty"), "~/src")), "term: ~/src");
+        assert_eq!(config.rewrite_title(&window(Some("foot"), "~/src")), "~/src");
+        assert_eq!(config.rewrite_title(&window(None, "~/src")), "~/src");
+    }
+
+    #[test]
+    fn rewrite_first_matching_rule_wins() {
+        let config = rewriting(json!([
+            {"app-id": "firefox", "title": "* — Mozilla Firefox", "replace": "web: $1"},
+            {"title": "* — Mozilla Firefox", "replace": "other: $1"},
+            {"title": "*", "replace": "any: $1"},
+        ]));
+        assert_eq!(config.rewrite_title(&window(Some("firefox"), "Docs — Mozilla Firefox")), "web: Docs");
+        assert_eq!(config.rewrite_title(&window(Some("librewolf"), "Docs — Mozilla Firefox")), "other: Docs");
+        assert_eq!(config.rewrite_title(&window(Some("firefox"), "Library")), "any: Library");
+    }
+
+    #[test]
+    fn rewrite_without_a_match_keeps_the_title() {
+        let config = rewriting(json!([{"title": "* - Chromium", "replace": "$1"}]));
+        assert_eq!(config.rewrite_title(&window(None, "Docs - Firefox")), "Docs - Firefox");
+    }
+}
@@ -0,0 +1,237 @@
+//! Config key spellings and schema versions.
+//!
+//! Keys are kebab-case like the rest of Waybar; the older snake_case spellings
+//! still work but are reported as deprecated. `config-version` names the schema
+//! a config was written for, and configs without it are version 1. Each entry
+//! in `MIGRATIONS` upgrades a config by one version, so older configs keep
+//! working and get told what to change.
+
+use crate::validate::{Diagnostic, RawConfig, CONFIG_KEYS};
+use serde_json::Value;
+
+const CURRENT_VERSION: u64 = 2;
+const VERSION_KEY: &str = "config-version";
+
+/// Options that only exist in older schema versions
+const LEGACY_KEYS: &[&str] = &["show-empty-workspace"];
+
+/// Top-level options holding an object, or array of objects, whose keys are options too
+const NESTED_OPTION_KEYS: &[&str] = &["ignore-rules", "window-title"];
+
+/// Options inside `ignore-rules`, `window-title` and its `rewrite` rules
+const NESTED_KEYS: &[&str] = &["app-id", "title", "format", "max-length", "rewrite", "replace"];
+
+/// Migrations from version N to N + 1, at index N - 1
+const MIGRATIONS: &[fn(&mut RawConfig, &mut Vec<Diagnostic>)] = &[migrate_show_empty_workspace];
+
+/// Normalize key spellings and upgrade the config to the current schema
+pub(crate) fn migrate(raw: &mut RawConfig, diagnostics: &mut Vec<Diagnostic>) {
+    normalize_keys(raw, "", diagnostics);
+
+    let version = match raw.remove(VERSION_KEY) {
+        None => 1,
+        Some(value) => match value.as_u64() {
+            Some(version) if (1..=CURRENT_VERSION).contains(&version) => version,
+            _ => {
+                diagnostics.push(Diagnostic::new(
+                    VERSION_KEY,
+                    format!("expected a version from 1 to {}, got {}; reading it as version {}", CURRENT_VERSION, value, CURRENT_VERSION),
+                ));
+                CURRENT_VERSION
+            }
+        },
+    };
+
+    for migration in &MIGRATIONS[(version - 1) as usize..] {
+        migration(raw, diagnostics);
+    }
+}
+
+fn is_known_key(key: &str, path: &str) -> bool {
+    if path.is_empty() {
+        CONFIG_KEYS.contains(&key) || LEGACY_KEYS.contains(&key) || key == VERSION_KEY
+    } else {
+        NESTED_KEYS.contains(&key)
+    }
+}
+
+/// Rename snake_case spellings of known keys to kebab-case, here and in nested option objects
+fn normalize_keys(object: &mut RawConfig, path: &str, diagnostics: &mut Vec<Diagnostic>) {
+    // Unknown keys are left alone: they may be Waybar's own, or workspace names in `format-<name>`
+    let snake_keys: Vec<String> = object
+        .keys()
+        .filter(|key| key.contains('_') && is_known_key(&key.replace('_', "-"), path))
+        .cloned()
+        .collect();
+    for key in snake_keys {
+        let kebab = key.replace('_', "-");
+        let value = object.remove(&key).expect("key was just listed");
+        let full_key = format!("{}{}", path, key);
+        if object.contains_key(&kebab) {
+            diagnostics.push(Diagnostic::new(full_key, format!("ignored because `{}{}` is also set", path, kebab)));
+        } else {
+            // Legacy options get a single warning from their migration instead
+            if !LEGACY_KEYS.contains(&kebab.as_str()) {
+                diagnostics.push(Diagnostic::new(full_key, format!("deprecated spelling, use `{}{}`", path, kebab)));
+            }
+            object.insert(kebab, value);
+        }
+    }
+
+    // Only recurse into objects of options; other maps are keyed by workspace, output or app names
+    if !path.is_empty() {
+        return;
+    }
+    for key in NESTED_OPTION_KEYS {
+        let Some(value) = object.get_mut(*key) else {
+            continue;
+        };
+        match value {
+            Value::Object(nested) => normalize_keys(nested, &format!("{}.", key), diagnostics),
+            Value::Array(items) => {
+                for item in items.iter_mut().filter_map(Value::as_object_mut) {
+                    normalize_keys(item, &format!("{}.", key), diagnostics);
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(rules) = object
+        .get_mut("window-title")
+        .and_then(Value::as_object_mut)
+        .and_then(|title| title.get_mut("rewrite"))
+        .and_then(Value::as_array_mut)
+    {
+        for rule in rules.iter_mut().filter_map(Value::as_object_mut) {
+            normalize_keys(rule, "window-title.rewrite.", diagnostics);
+        }
+    }
+}
+
+/// Version 1 to 2: `show-empty-workspace` is replaced by `workspace-visibility`
+fn migrate_show_empty_workspace(raw: &mut RawConfig, diagnostics: &mut Vec<Diagnostic>) {
+    let Some(show_empty) = raw.remove("show-empty-workspace") else {
+        return;
+    };
+    if raw.contains_key("workspace-visibility") {
+        diagnostics.push(Diagnostic::new(
+            "show-empty-workspace",
+            "ignored because `workspace-visibility` is also set",
+        ));
+        return;
+    }
+
+    let visibility = match show_empty.as_bool() {
+        Some(true) => "occupied_trailing",
+        Some(false) => "occupied_focused",
+        None => {
+            diagnostics.push(Diagnostic::new("show-empty-workspace", format!("expected a boolean, got {}; ignored", show_empty)));
+            return;
+        }
+    };
+    diagnostics.push(Diagnostic::new(
+        "show-empty-workspace",
+        format!("deprecated, use `\"workspace-visibility\": \"{}\"`", visibility),
+    ));
+    raw.insert("workspace-visibility".to_string(), Value::String(visibility.to_string()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::migrate;
+    use serde_json::json;
+
+    fn migrated(config: serde_json::Value) -> (serde_json::Value, Vec<String>) {
+        let serde_json::Value::Object(mut raw) = config else {
+            panic!("config must be an object");
+        };
+        let mut diagnostics = Vec::new();
+        migrate(&mut raw, &mut diagnostics);
+        (serde_json::Value::Object(raw), diagnostics.iter().map(ToString::to_string).collect())
+    }
+
+    #[test]
+    fn snake_case_keys_are_renamed() {
+        let (raw, diagnostics) = migrated(json!({
+            "config-version": 2,
+            "all_outputs": true,
+            "ignore_rules": [{"app_id": "xpad"}],
+            "window_title": {"max_length": 30, "rewrite": [{"app_id": "firefox", "replace": "web"}]},
+        }));
+        assert_eq!(raw, json!({
+            "all-outputs": true,
+            "ignore-rules": [{"app-id": "xpad"}],
+            "window-title": {"max-length": 30, "rewrite": [{"app-id": "firefox", "replace": "web"}]},
+        }));
+        assert_eq!(diagnostics.len(), 6);
+        assert!(diagnostics.contains(&"`all_outputs`: deprecated spelling, use `all-outputs`".to_string()));
+        assert!(diagnostics.contains(&"`ignore-rules.app_id`: deprecated spelling, use `ignore-rules.app-id`".to_string()));
+        assert!(diagnostics.contains(
+            &"`window-title.rewrite.app_id`: deprecated spelling, use `window-title.rewrite.app-id`".to_string()
+        ));
+    }
+
+    #[test]
+    fn other_keys_are_left_alone() {
+        let config = json!({
+            "config-version": 2,
+            "module_path": "/usr/lib/libniri_workspaces.so",
+            "format-my_ws": "{name}",
+            "format-icons": {"my_ws": "a"},
+            "persistent-workspaces": {"DP_1": ["chat_work"]},
+            "not_an_option": 1,
+        });
+        let (raw, diagnostics) = migrated(config.clone());
+        let mut expected = config;
+        expected.as_object_mut().unwrap().remove("config-version");
+        assert_eq!(raw, expected);
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn kebab_case_wins_over_snake_case() {
+        let (raw, diagnostics) = migrated(json!({"config-version": 2, "all_outputs": true, "all-outputs": false}));
+        assert_eq!(raw, json!({"all-outputs": false}));
+        assert_eq!(diagnostics, ["`all_outputs`: ignored because `all-outputs` is also set"]);
+    }
+
+    #[test]
+    fn config_version_bounds() {
+        // Version 2 configs skip the version 1 migration
+        let (raw, diagnostics) = migrated(json!({"config-version": 2, "show-empty-workspace": true}));
+        assert_eq!(raw, json!({"show-empty-workspace": true}));
+        assert!(diagnostics.is_empty());
+
+        for version in [json!(0), json!(3), json!("2"), json!(-1)] {
+            let (raw, diagnostics) = migrated(json!({"config-version": version.clone(), "show-empty-workspace": true}));
+            assert_eq!(raw, json!({"show-empty-workspace": true}));
+            assert_eq!(
+                diagnostics,
+                [format!("`config-version`: expected a version from 1 to 2, got {}; reading it as version 2", version)]
+            );
+        }
+    }
+
+    #[test]
+    fn show_empty_workspace_is_migrated() {
+        let (raw, diagnostics) = migrated(json!({"show-empty-workspace": true}));
+        assert_eq!(raw, json!({"workspace-visibility": "occupied_trailing"}));
+        assert_eq!(
+            diagnostics,
+            ["`show-empty-workspace`: deprecated, use `\"workspace-visibility\": \"occupied_trailing\"`"]
+        );
+
+        // The snake_case spelling gets a single warning, from the migration
+        let (raw, diagnostics) = migrated(json!({"config-version": 1, "show_empty_workspace": false}));
+        assert_eq!(raw, json!({"workspace-visibility": "occupied_focused"}));
+        assert_eq!(diagnostics.len(), 1);
+
+        let (raw, diagnostics) = migrated(json!({"show-empty-workspace": true, "workspace-visibility": "all"}));
+        assert_eq!(raw, json!({"workspace-visibility": "all"}));
+        assert_eq!(diagnostics, ["`show-empty-workspace`: ignored because `workspace-visibility` is also set"]);
+
+        let (raw, diagnostics) = migrated(json!({"show-empty-workspace": "yes"}));
+        assert_eq!(raw, json!({}));
+        assert_eq!(diagnostics, ["`show-empty-workspace`: expected a boolean, got \"yes\"; ignored"]);
+    }
+}
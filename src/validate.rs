@@ -0,0 +1,451 @@
+//! Config parsing with diagnostics.
+//!
+//! Waybar hands the module every key from its config block, including its own
+//! generic ones, so unknown keys are reported with a suggestion rather than
+//! rejected. A key with an invalid value is reported and falls back to its
+//! default instead of failing the whole module.
+
+use crate::{schema, Config};
+use std::fmt;
+
+/// The module config block as Waybar passes it
+pub(crate) type RawConfig = serde_json::Map<String, serde_json::Value>;
+
+/// Keys handled by `Config`, besides `format-<state>` and `format-<name>`
+pub(crate) const CONFIG_KEYS: &[&str] = &[
+    "ignore-rules",
+    "workspace-visibility",
+    "min-workspaces",
+    "always-show-named",
+    "persistent-workspaces",
+    "window-title",
+    "urgent-blink-interval",
+    "urgent-auto-focus-delay",
+    "urgent-indicator",
+    "overview-button",
+    "icon-size",
+    "pie-glyphs",
+    "pie-colors",
+    "pie-thresholds",
+    "pie-css-classes",
+    "pie-mode",
+    "pie-segments",
+    "pie-size",
+    "pie-app-colors",
+    "window-indicators",
+    "window-indicators-max",
+    "all-outputs",
+    "empty-detection",
+    "format",
+    "format-icons",
+    "icon-priority",
+    "combine-icons",
+    "disable-click",
+    "current-only",
+    "drag-hover-focus",
+    "drag-hover-focus-delay",
+    "hover-peek",
+    "hover-peek-delay",
+    "workspace-transition",
+    "workspace-transition-duration",
+    "render-coalescing",
+    "max-render-rate",
+    "metrics-interval",
+    "dbus",
+];
+
+/// Keys Waybar itself understands for any module
+const WAYBAR_KEYS: &[&str] = &[
+    "module_path",
+    "actions",
+    "align",
+    "expand",
+    "interval",
+    "justify",
+    "max-length",
+    "menu",
+    "menu-actions",
+    "menu-file",
+    "min-length",
+    "on-click",
+    "on-click-backward",
+    "on-click-forward",
+    "on-click-middle",
+    "on-click-release",
+    "on-click-right",
+    "on-double-click",
+    "on-scroll-down",
+    "on-scroll-left",
+    "on-scroll-right",
+    "on-scroll-up",
+    "on-triple-click",
+    "on-update",
+    "rotate",
+    "signal",
+    "smooth-scrolling-threshold",
+    "tooltip",
+];
+
+/// Keys of the objects nested in options, by path; `[]` stands for any array element
+const NESTED_KEYS: &[(&str, &[&str])] = &[
+    ("ignore-rules[]", &["app-id", "title"]),
+    ("window-title", &["format", "max-length", "rewrite"]),
+    ("window-title.rewrite[]", &["app-id", "title", "replace"]),
+];
+
+const STATE_ICON_KEYS: &[&str] = &["urgent", "empty", "focused", "active", "default"];
+
+const ICON_SIZES: &[&str] = &[
+    "xx-small", "x-small", "small", "medium", "large", "x-large", "xx-large", "smaller", "larger",
+];
+
+const MAX_DELAY_MS: u32 = 10_000;
+const MIN_BLINK_INTERVAL_MS: u32 = 50;
+
+/// A problem found in the config, tied to the key it came from
+pub(crate) struct Diagnostic {
+    key: String,
+    message: String,
+}
+
+impl Diagnostic {
+    pub(crate) fn new(key: impl Into<String>, message: impl Into<String>) -> Diagnostic {
+        Diagnostic { key: key.into(), message: message.into() }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}`: {}", self.key, self.message)
+    }
+}
+
+/// Parse the config, logging every problem found and using defaults in their place
+pub(crate) fn parse(raw: RawConfig) -> Config {
+    let (config, diagnostics) = diagnose(raw);
+    for diagnostic in &diagnostics {
+        eprintln!("niri_workspaces config: {}", diagnostic);
+    }
+    config
+}
+
+/// The config with defaults in place of invalid values, and the problems found
+fn diagnose(mut raw: RawConfig) -> (Config, Vec<Diagnostic>) {
+    let mut diagnostics = Vec::new();
+    schema::migrate(&mut raw, &mut diagnostics);
+
+    let mut valid = RawConfig::new();
+
+    for (key, value) in raw {
+        if !is_config_key(&key) {
+            if !WAYBAR_KEYS.contains(&key.as_str()) {
+                diagnostics.push(unknown_key(&key, &key, CONFIG_KEYS.iter().chain(WAYBAR_KEYS)));
+            }
+            continue;
+        }
+        check_nested(&key, &key, &value, &mut diagnostics);
+
+        // Parse each key on its own so one bad value doesn't take the others down
+        let single = RawConfig::from_iter([(key.clone(), value.clone())]);
+        match serde_json::from_value::<Config>(single.into()) {
+            Ok(_) => {
+                valid.insert(key, value);
+            }
+            Err(e) => diagnostics.push(Diagnostic::new(key, format!("{}; using the default", e))),
+        }
+    }
+
+    let mut config = match serde_json::from_value::<Config>(valid.into()) {
+        Ok(config) => config,
+        Err(e) => {
+            diagnostics.push(Diagnostic::new("cffi/niri_workspaces", format!("{}; using the default config", e)));
+            serde_json::from_value(RawConfig::new().into()).expect("the default config is valid")
+        }
+    };
+    check(&mut config, &mut diagnostics);
+    (config, diagnostics)
+}
+
+fn is_config_key(key: &str) -> bool {
+    CONFIG_KEYS.contains(&key) || key.starts_with("format-")
+}
+
+/// `key` at `path` isn't one of `candidates`
+fn unknown_key<'a>(path: &str, key: &str, candidates: impl IntoIterator<Item = &'a &'a str>) -> Diagnostic {
+    let message = match suggest(key, candidates) {
+        Some(suggestion) => format!("unknown option, did you mean `{}`?", suggestion),
+        None => "unknown option, ignored".to_string(),
+    };
+    Diagnostic::new(path, message)
+}
+
+/// Report unknown keys in the objects nested in `value`, which serde would silently drop.
+/// `schema` is the path in `NESTED_KEYS` terms, `path` the one shown to the user.
+fn check_nested(schema: &str, path: &str, value: &serde_json::Value, diagnostics: &mut Vec<Diagnostic>) {
+    match value {
+        serde_json::Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                check_nested(&format!("{}[]", schema), &format!("{}[{}]", path, i), item, diagnostics);
+            }
+        }
+        serde_json::Value::Object(map) => {
+            let Some((_, keys)) = NESTED_KEYS.iter().find(|(nested, _)| *nested == schema) else {
+                return;
+            };
+            for (key, value) in map {
+                let key_path = format!("{}.{}", path, key);
+                if keys.contains(&key.as_str()) {
+                    check_nested(&format!("{}.{}", schema, key), &key_path, value, diagnostics);
+                } else {
+                    diagnostics.push(unknown_key(&key_path, key, keys.iter()));
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Checks that need the parsed values; invalid values are replaced so the module still works
+fn check(config: &mut Config, diagnostics: &mut Vec<Diagnostic>) {
+    if let Some(size) = &config.icon_size {
+        if !is_icon_size(size) {
+            diagnostics.push(Diagnostic::new(
+                "icon-size",
+                format!("'{}' is not a Pango font size (e.g. \"large\", \"12pt\" or \"150%\"); ignored", size),
+            ));
+            config.icon_size = None;
+        }
+    }
+
+    // A rule without matchers would hide every window
+    let mut index = 0;
+    config.ignore_rules.retain(|rule| {
+        let matches_all = rule.app_id.is_none() && rule.title.is_none();
+        if matches_all {
+            diagnostics.push(Diagnostic::new(
+                format!("ignore-rules[{}]", index),
+                "has neither `app-id` nor `title`, so it would match every window; ignored",
+            ));
+        }
+        index += 1;
+        !matches_all
+    });
+
+    for (key, delay) in [
+        ("drag-hover-focus-delay", &mut config.drag_hover_focus_delay),
+        ("hover-peek-delay", &mut config.hover_peek_delay),
+        ("workspace-transition-duration", &mut config.workspace_transition_duration),
+    ] {
+        if *delay > MAX_DELAY_MS {
+            diagnostics.push(Diagnostic::new(key, format!("{} ms is too long; using {} ms", delay, MAX_DELAY_MS)));
+            *delay = MAX_DELAY_MS;
+        }
+    }
+
+    if let Some(interval) = &mut config.urgent_blink_interval {
+        if *interval < MIN_BLINK_INTERVAL_MS {
+            diagnostics.push(Diagnostic::new(
+                "urgent-blink-interval",
+                format!("{} ms is too short; using {} ms", interval, MIN_BLINK_INTERVAL_MS),
+            ));
+            *interval = MIN_BLINK_INTERVAL_MS;
+        }
+    }
+
+    if config.pie_colors.len() != config.pie_thresholds.len() {
+        diagnostics.push(Diagnostic::new(
+            "pie-colors",
+            format!(
+                "has {} entries but pie-thresholds has {}; extra entries are ignored",
+                config.pie_colors.len(),
+                config.pie_thresholds.len()
+            ),
+        ));
+    }
+    // Thresholds may come in any order; keep each with its color, lowest first
+    let mut bands: Vec<(usize, String)> =
+        config.pie_thresholds.iter().copied().zip(config.pie_colors.drain(..)).collect();
+    bands.sort_by_key(|(threshold, _)| *threshold);
+    (config.pie_thresholds, config.pie_colors) = bands.into_iter().unzip();
+
+    // Icon keys that aren't states are workspace names, so only flag near-misses of a state
+    if let Some(icons) = &config.format_icons {
+        let declared: Vec<&String> = config.persistent_workspaces.values().flatten().collect();
+        for key in icons.named.keys() {
+            if declared.contains(&key) {
+                continue;
+            }
+            let state = key.split_once('-').map_or(key.as_str(), |(state, _)| state);
+            if STATE_ICON_KEYS.contains(&state) {
+                continue;
+            }
+            if let Some(suggestion) = suggest(state, STATE_ICON_KEYS) {
+                diagnostics.push(Diagnostic::new(
+                    format!("format-icons.{}", key),
+                    format!("treated as a workspace name; did you mean the `{}` state?", suggestion),
+                ));
+            }
+        }
+    }
+}
+
+fn is_icon_size(size: &str) -> bool {
+    if ICON_SIZES.contains(&size) {
+        return true;
+    }
+    // Points and percentages, or a whole number of 1024ths of a point
+    match size.strip_suffix("pt").or_else(|| size.strip_suffix('%')) {
+        Some(number) => number.parse::<f64>().is_ok_and(|n| n > 0.0),
+        None => size.parse::<u32>().is_ok_and(|n| n > 0),
+    }
+}
+
+/// The closest candidate within a few edits of `input`
+fn suggest<'a>(input: &str, candidates: impl IntoIterator<Item = &'a &'a str>) -> Option<&'a str> {
+    let max_distance = (input.chars().count() / 3).max(2);
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(input, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// The parsed config and its diagnostics as logged
+    fn diagnosed(value: serde_json::Value) -> (Config, Vec<String>) {
+        let raw = value.as_object().expect("the test config is an object").clone();
+        let (config, diagnostics) = diagnose(raw);
+        (config, diagnostics.iter().map(ToString::to_string).collect())
+    }
+
+    #[test]
+    fn suggests_close_keys_only() {
+        assert_eq!(suggest("workspace-visiblity", CONFIG_KEYS), Some("workspace-visibility"));
+        assert_eq!(suggest("all-output", CONFIG_KEYS), Some("all-outputs"));
+        assert_eq!(suggest("frobnicate", CONFIG_KEYS), None);
+    }
+
+    #[test]
+    fn edit_distance_counts_edits() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("focused", "focused"), 0);
+    }
+
+    #[test]
+    fn icon_sizes() {
+        for size in ["large", "12pt", "12.5pt", "150%", "12288"] {
+            assert!(is_icon_size(size), "{}", size);
+        }
+        for size in ["12.5", "huge", "0", "0pt", "-3pt", ""] {
+            assert!(!is_icon_size(size), "{}", size);
+        }
+    }
+
+    #[test]
+    fn invalid_value_falls_back_to_default() {
+        let (config, diagnostics) = diagnosed(json!({"min-workspaces": "three", "hover-peek": true}));
+        assert_eq!(config.min_workspaces, 0);
+        assert!(config.hover_peek);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].starts_with("`min-workspaces`: "), "{}", diagnostics[0]);
+        assert!(diagnostics[0].ends_with("; using the default"), "{}", diagnostics[0]);
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let (_, diagnostics) = diagnosed(json!({"all-output": true, "on-click": "x", "frobnicate": 1}));
+        assert_eq!(
+            diagnostics,
+            [
+                "`all-output`: unknown option, did you mean `all-outputs`?",
+                "`frobnicate`: unknown option, ignored",
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_nested_keys() {
+        let (config, diagnostics) = diagnosed(json!({
+            "ignore-rules": [{"app-id": "pavucontrol"}, {"app-idd": "xpad"}],
+            "window-title": {"max-lenght": 30, "rewrite": [{"title": "* - Firefox", "replce": "x", "replace": "$1"}]},
+        }));
+        assert_eq!(config.ignore_rules.len(), 1);
+        assert_eq!(
+            diagnostics,
+            [
+                "`ignore-rules[1].app-idd`: unknown option, did you mean `app-id`?",
+                "`window-title.max-lenght`: unknown option, did you mean `max-length`?",
+                "`window-title.rewrite[0].replce`: unknown option, did you mean `replace`?",
+                "`ignore-rules[1]`: has neither `app-id` nor `title`, so it would match every window; ignored",
+            ]
+        );
+    }
+
+    #[test]
+    fn clamps_delays_and_blink_interval() {
+        let (config, diagnostics) = diagnosed(json!({
+            "hover-peek-delay": 60000,
+            "workspace-transition-duration": 10000,
+            "urgent-blink-interval": 10,
+        }));
+        assert_eq!(config.hover_peek_delay, MAX_DELAY_MS);
+        assert_eq!(config.workspace_transition_duration, MAX_DELAY_MS);
+        assert_eq!(config.urgent_blink_interval, Some(MIN_BLINK_INTERVAL_MS));
+        assert_eq!(
+            diagnostics,
+            [
+                "`hover-peek-delay`: 60000 ms is too long; using 10000 ms",
+                "`urgent-blink-interval`: 10 ms is too short; using 50 ms",
+            ]
+        );
+    }
+
+    #[test]
+    fn format_icons_near_miss() {
+        let (_, diagnostics) = diagnosed(json!({
+            "format-icons": {"focsued": "a", "focused-dev": "b", "dev": "c", "actve-mail": "d", "urgnt": "e"},
+            "persistent-workspaces": {"*": ["urgnt"]},
+        }));
+        let mut diagnostics = diagnostics;
+        diagnostics.sort();
+        assert_eq!(
+            diagnostics,
+            [
+                "`format-icons.actve-mail`: treated as a workspace name; did you mean the `active` state?",
+                "`format-icons.focsued`: treated as a workspace name; did you mean the `focused` state?",
+            ]
+        );
+    }
+
+    #[test]
+    fn pie_thresholds_are_sorted_with_their_colors() {
+        let (config, diagnostics) = diagnosed(json!({
+            "pie-thresholds": [10, 3, 7, 12],
+            "pie-colors": ["red", "green", "yellow"],
+        }));
+        assert_eq!(config.pie_thresholds, [3, 7, 10]);
+        assert_eq!(config.pie_colors, ["green", "yellow", "red"]);
+        assert_eq!(diagnostics, ["`pie-colors`: has 3 entries but pie-thresholds has 4; extra entries are ignored"]);
+    }
+}